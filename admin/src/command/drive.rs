@@ -0,0 +1,44 @@
+use api::{endpoint::fairing::storage::DriveStorageConfig, storage::drive};
+use clap::Subcommand;
+
+use super::AdminResult;
+
+#[derive(Debug, Subcommand)]
+pub enum DriveCommand {
+    /// Upgrades all files of the drive storage to the current format and moves them into
+    /// their shard, files are replaced atomically so the api can keep running
+    Migrate,
+}
+
+/// Executes a drive storage command
+pub async fn run(command: DriveCommand) -> AdminResult<()> {
+    match command {
+        DriveCommand::Migrate => migrate().await,
+    }
+}
+
+async fn migrate() -> AdminResult<()> {
+    let config: DriveStorageConfig = rocket::Config::figment().focus("storage.drive").extract()?;
+    let layout = config.layout();
+    let ids = drive::list_files(&layout).await?;
+
+    let mut upgraded = 0;
+    let mut failed = 0;
+    for id in &ids {
+        match drive::upgrade_file(&layout, id).await {
+            Ok(true) => upgraded += 1,
+            Ok(false) => {}
+            Err(err) => {
+                eprintln!("Failed to upgrade file {}: {}", id, err);
+                failed += 1;
+            }
+        }
+    }
+    println!(
+        "Upgraded {} of {} files, {} failed",
+        upgraded,
+        ids.len(),
+        failed
+    );
+    Ok(())
+}
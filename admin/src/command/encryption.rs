@@ -0,0 +1,67 @@
+use api::{
+    database::query::file::{find_files_with_foreign_data_key, update_file_data_key},
+    endpoint::fairing::database::PostgresPool,
+    storage::encryption::{EncryptionConfig, Keyring},
+};
+use clap::Subcommand;
+
+use super::AdminResult;
+
+/// Amount of files re-wrapped per transaction
+const ROTATION_BATCH_SIZE: i64 = 100;
+
+#[derive(Debug, Subcommand)]
+pub enum EncryptionCommand {
+    /// Generates a new master key which can be used as `master_key`
+    GenerateKey,
+    /// Re-wraps all data keys with the current master key, the stored files are not touched.
+    /// Move the replaced key to `previous_master_keys` before running this
+    Rotate,
+}
+
+/// Executes an encryption command
+pub async fn run(command: EncryptionCommand, pool: &PostgresPool) -> AdminResult<()> {
+    match command {
+        EncryptionCommand::GenerateKey => {
+            println!("{}", Keyring::generate_master_key());
+            Ok(())
+        }
+        EncryptionCommand::Rotate => rotate(pool).await,
+    }
+}
+
+async fn rotate(pool: &PostgresPool) -> AdminResult<()> {
+    let config: EncryptionConfig = rocket::Config::figment()
+        .focus("storage.encryption")
+        .extract()?;
+    let keyring = Keyring::from_config(&config)?;
+    let key_id = keyring
+        .current_key_id()
+        .ok_or("No master key is configured")?
+        .to_string();
+
+    let mut rotated = 0;
+    loop {
+        let mut transaction = pool.begin().await?;
+        let files =
+            find_files_with_foreign_data_key(&mut transaction, &key_id, ROTATION_BATCH_SIZE)
+                .await?;
+        if files.is_empty() {
+            break;
+        }
+        for file in &files {
+            let wrapped = file.data_key.as_deref().unwrap_or_default();
+            let data_key = keyring
+                .rewrap_data_key(wrapped)
+                .map_err(|err| format!("Failed to re-wrap key of file {}: {}", file.id, err))?;
+            update_file_data_key(&mut transaction, &file.id, &data_key).await?;
+        }
+        transaction.commit().await?;
+        rotated += files.len();
+    }
+    println!(
+        "Re-wrapped {} data keys with master key {}",
+        rotated, key_id
+    );
+    Ok(())
+}
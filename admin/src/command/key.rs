@@ -0,0 +1,94 @@
+use api::{
+    database::query::api_key::{
+        list_api_keys, revoke_api_key_by_name, rotate_api_key_by_name, save_api_key,
+    },
+    endpoint::{
+        fairing::database::PostgresPool,
+        v1::auth::{generate_api_key, hash_api_key},
+    },
+};
+use clap::Subcommand;
+use uuid::Uuid;
+
+use super::{format_timestamp, AdminResult};
+
+#[derive(Debug, Subcommand)]
+pub enum KeyCommand {
+    /// Creates a new api key, the key is only shown once
+    Create { name: String },
+    /// Lists all api keys and the files uploaded using them
+    List,
+    /// Revokes an api key, it can no longer be used for uploading
+    Revoke { name: String },
+    /// Replaces an api key with a newly generated one, the old key stops working
+    Rotate { name: String },
+}
+
+/// Executes an api key command
+pub async fn run(command: KeyCommand, pool: &PostgresPool) -> AdminResult<()> {
+    match command {
+        KeyCommand::Create { name } => create(pool, name).await,
+        KeyCommand::List => list(pool).await,
+        KeyCommand::Revoke { name } => revoke(pool, name).await,
+        KeyCommand::Rotate { name } => rotate(pool, name).await,
+    }
+}
+
+async fn create(pool: &PostgresPool, name: String) -> AdminResult<()> {
+    let mut transaction = pool.begin().await?;
+    let id = Uuid::new_v4().to_string().replace("-", "");
+    let key = generate_api_key();
+
+    save_api_key(&mut transaction, &id, &name, &hash_api_key(&key)).await?;
+    transaction.commit().await?;
+    println!("Created api key '{}': {}", name, key);
+    Ok(())
+}
+
+async fn list(pool: &PostgresPool) -> AdminResult<()> {
+    let mut transaction = pool.begin().await?;
+    let keys = list_api_keys(&mut transaction).await?;
+
+    println!(
+        "{:<24} {:<32} {:<20} {:<20} {:>8} {:>14}",
+        "NAME", "ID", "CREATED", "REVOKED", "FILES", "BYTES"
+    );
+    for usage in keys {
+        println!(
+            "{:<24} {:<32} {:<20} {:<20} {:>8} {:>14}",
+            usage.key.name,
+            usage.key.id,
+            format_timestamp(usage.key.created_at),
+            usage
+                .key
+                .revoked_at
+                .map(format_timestamp)
+                .unwrap_or("-".into()),
+            usage.file_count,
+            usage.total_size
+        );
+    }
+    Ok(())
+}
+
+async fn revoke(pool: &PostgresPool, name: String) -> AdminResult<()> {
+    let mut transaction = pool.begin().await?;
+    revoke_api_key_by_name(&mut transaction, &name)
+        .await
+        .map_err(|_| format!("No active api key named '{}'", name))?;
+    transaction.commit().await?;
+    println!("Revoked api key '{}'", name);
+    Ok(())
+}
+
+async fn rotate(pool: &PostgresPool, name: String) -> AdminResult<()> {
+    let mut transaction = pool.begin().await?;
+    let key = generate_api_key();
+
+    rotate_api_key_by_name(&mut transaction, &name, &hash_api_key(&key))
+        .await
+        .map_err(|_| format!("No active api key named '{}'", name))?;
+    transaction.commit().await?;
+    println!("Rotated api key '{}': {}", name, key);
+    Ok(())
+}
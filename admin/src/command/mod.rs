@@ -0,0 +1,13 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+pub mod drive;
+pub mod encryption;
+pub mod key;
+pub mod storage;
+
+pub type AdminResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Formats a timestamp (ms since the unix epoch) as a human readable date
+fn format_timestamp(ms: i64) -> String {
+    humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_millis(ms as u64)).to_string()
+}
@@ -0,0 +1,240 @@
+use std::sync::Arc;
+
+use api::{
+    database::query::file::{list_pending_upload_ids, list_storage_ids},
+    endpoint::{
+        fairing::{
+            database::PostgresPool,
+            reconciler::{reconcile_storage, DEFAULT_ORPHAN_GRACE_PERIOD},
+            storage::{StorageBackendRegistry, StorageDriverType},
+        },
+        v1::hash_file_stream,
+    },
+    storage::{backend::StorageBackend, driver::StorageTier},
+};
+use clap::Subcommand;
+
+use super::{format_timestamp, AdminResult};
+
+/// Amount of storage objects loaded per query
+const MIGRATION_BATCH_SIZE: i64 = 100;
+
+#[derive(Debug, Subcommand)]
+pub enum StorageCommand {
+    /// Copies the objects of all files from one storage type to another and verifies their
+    /// size and hash. Objects already present in the target are skipped, so an interrupted
+    /// migration can be resumed by running it again
+    Migrate {
+        /// The storage type the files are currently stored in
+        #[arg(long)]
+        from: String,
+        /// The storage type the files are copied to
+        #[arg(long)]
+        to: String,
+        /// Migrates the files of the cold tier instead of the hot tier
+        #[arg(long)]
+        cold: bool,
+    },
+    /// Lists objects which aren't used by any file and flags files whose objects are missing.
+    /// Objects found by previous runs are deleted once they are older than the grace period
+    Reconcile {
+        /// Deletes orphaned objects which are older than the grace period
+        #[arg(long)]
+        delete: bool,
+        /// Time in seconds objects have to be orphaned before they are deleted, defaults to
+        /// `orphan_grace_period`
+        #[arg(long)]
+        grace_period: Option<u64>,
+    },
+}
+
+/// Outcome of migrating a single storage object
+enum Migration {
+    Copied,
+    Skipped,
+}
+
+/// Kind of storage objects which are migrated
+#[derive(Clone, Copy)]
+enum MigratedObjects {
+    // Objects of the files in a tier
+    Files(StorageTier),
+    // Objects clients upload pending direct uploads to, they may not exist yet
+    Uploads,
+}
+
+/// Executes a storage command
+pub async fn run(command: StorageCommand, pool: &PostgresPool) -> AdminResult<()> {
+    match command {
+        StorageCommand::Migrate { from, to, cold } => {
+            let tier = if cold {
+                StorageTier::Cold
+            } else {
+                StorageTier::Hot
+            };
+            migrate(pool, from, to, tier).await
+        }
+        StorageCommand::Reconcile {
+            delete,
+            grace_period,
+        } => reconcile(pool, delete, grace_period).await,
+    }
+}
+
+async fn migrate(
+    pool: &PostgresPool,
+    from: String,
+    to: String,
+    tier: StorageTier,
+) -> AdminResult<()> {
+    let source = create_backend(from)?;
+    let target = create_backend(to)?;
+
+    // Direct uploads are always stored in the hot tier
+    let mut kinds = vec![MigratedObjects::Files(tier)];
+    if tier == StorageTier::Hot {
+        kinds.push(MigratedObjects::Uploads);
+    }
+    let (mut copied, mut skipped, mut failed) = (0, 0, 0);
+    for kind in kinds {
+        let mut after = None;
+        loop {
+            let ids = list_migrated_objects(pool, kind, after.as_ref()).await?;
+            let Some(last) = ids.last().cloned() else {
+                break;
+            };
+
+            for id in &ids {
+                match migrate_object(&source, &target, id, kind).await {
+                    Ok(Migration::Copied) => copied += 1,
+                    Ok(Migration::Skipped) => skipped += 1,
+                    Err(err) => {
+                        eprintln!("Failed to migrate object {}: {}", id, err);
+                        failed += 1;
+                    }
+                }
+            }
+            println!("Migrated objects up to {}", last);
+            after = Some(last);
+        }
+    }
+    println!(
+        "Copied {} objects, {} were already migrated, {} failed",
+        copied, skipped, failed
+    );
+    Ok(())
+}
+
+/// Lists the next batch of objects to migrate
+async fn list_migrated_objects(
+    pool: &PostgresPool,
+    kind: MigratedObjects,
+    after: Option<&String>,
+) -> AdminResult<Vec<String>> {
+    let mut transaction = pool.begin().await?;
+    let ids = match kind {
+        MigratedObjects::Files(tier) => {
+            list_storage_ids(
+                &mut transaction,
+                Some(tier.as_str()),
+                after,
+                MIGRATION_BATCH_SIZE,
+            )
+            .await?
+        }
+        MigratedObjects::Uploads => {
+            list_pending_upload_ids(&mut transaction, after, MIGRATION_BATCH_SIZE).await?
+        }
+    };
+    transaction.commit().await?;
+    Ok(ids)
+}
+
+async fn reconcile(
+    pool: &PostgresPool,
+    delete: bool,
+    grace_period: Option<u64>,
+) -> AdminResult<()> {
+    let figment = rocket::Config::figment();
+    let storage = StorageBackendRegistry::default().create_driver(&figment)?;
+    let grace_period = grace_period
+        .or_else(|| figment.extract_inner("orphan_grace_period").ok())
+        .unwrap_or(DEFAULT_ORPHAN_GRACE_PERIOD);
+    let report = reconcile_storage(pool, &storage, grace_period, delete).await?;
+
+    for orphan in &report.orphans {
+        println!(
+            "Orphaned object {} in the {} tier, found at {}",
+            orphan.storage_id,
+            orphan.tier,
+            format_timestamp(orphan.found_at)
+        );
+    }
+    for storage_id in &report.missing {
+        println!("Missing object {}", storage_id);
+    }
+    println!(
+        "Found {} orphaned objects ({} deleted) and {} missing objects",
+        report.orphans.len(),
+        report.deleted,
+        report.missing.len()
+    );
+    Ok(())
+}
+
+/// Copies a single object, the copy is deleted again if it doesn't match the original
+async fn migrate_object(
+    source: &Arc<dyn StorageBackend>,
+    target: &Arc<dyn StorageBackend>,
+    id: &str,
+    kind: MigratedObjects,
+) -> AdminResult<Migration> {
+    // Clients may not have uploaded the object of a pending upload yet
+    if matches!(kind, MigratedObjects::Uploads) && !source.file_exists(id).await? {
+        return Ok(Migration::Skipped);
+    }
+    // Files uploaded after switching the storage type are only stored in the target
+    let metadata = match (source.head_file(id).await, target.head_file(id).await) {
+        (Ok(metadata), Ok(existing)) if existing.size == metadata.size => {
+            return Ok(Migration::Skipped)
+        }
+        (Ok(metadata), _) => metadata,
+        (Err(_), Ok(_)) => return Ok(Migration::Skipped),
+        (Err(err), Err(_)) => return Err(err.into()),
+    };
+
+    // Objects are copied as stored, so encrypted objects stay encrypted with their data key
+    let (stream, content_type) = source.get_file(id, None).await?;
+    target
+        .save_file(id, &content_type, metadata.size, stream)
+        .await?;
+
+    let verified = async {
+        let copy = target.head_file(id).await?;
+        if copy.size != metadata.size {
+            return Err(format!("size is {} instead of {}", copy.size, metadata.size).into());
+        }
+        let (original, _) = source.get_file(id, None).await?;
+        let (copy, _) = target.get_file(id, None).await?;
+        if hash_file_stream(original).await? != hash_file_stream(copy).await? {
+            return Err("hash of the copy doesn't match".into());
+        }
+        AdminResult::Ok(())
+    }
+    .await;
+    if let Err(err) = verified {
+        let _ = target.delete_file(id).await;
+        return Err(format!("Copy is invalid, {}", err).into());
+    }
+    Ok(Migration::Copied)
+}
+
+/// Creates the backend of a storage type using the configuration in `Rocket.toml`
+fn create_backend(driver_type: String) -> AdminResult<Arc<dyn StorageBackend>> {
+    let driver_type = StorageDriverType::new(driver_type);
+    let backend = StorageBackendRegistry::default()
+        .create(&driver_type, &rocket::Config::figment())
+        .ok_or_else(|| format!("Unknown storage type {}", driver_type))?
+        .map_err(|err| format!("Unable to load {} storage config: {}", driver_type, err))?;
+    Ok(backend)
+}
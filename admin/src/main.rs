@@ -0,0 +1,46 @@
+use api::endpoint::fairing::database::{connect, PostgresConfig};
+use clap::{Parser, Subcommand};
+use command::{
+    drive::DriveCommand, encryption::EncryptionCommand, key::KeyCommand, storage::StorageCommand,
+    AdminResult,
+};
+
+mod command;
+
+/// Administration utility for the uploader service, reads the same `Rocket.toml` as the api
+#[derive(Debug, Parser)]
+#[command(name = "uploader-admin", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Manage the api keys used for uploading files
+    #[command(subcommand)]
+    Key(KeyCommand),
+    /// Manage the keys used for encrypting stored files
+    #[command(subcommand)]
+    Encryption(EncryptionCommand),
+    /// Manage the files of the drive storage
+    #[command(subcommand)]
+    Drive(DriveCommand),
+    /// Move files between storage types
+    #[command(subcommand)]
+    Storage(StorageCommand),
+}
+
+#[tokio::main]
+async fn main() -> AdminResult<()> {
+    let cli = Cli::parse();
+    let config: PostgresConfig = rocket::Config::figment().focus("database").extract()?;
+    let pool = connect(&config).await?;
+
+    match cli.command {
+        Command::Key(command) => command::key::run(command, &pool).await,
+        Command::Encryption(command) => command::encryption::run(command, &pool).await,
+        Command::Drive(command) => command::drive::run(command).await,
+        Command::Storage(command) => command::storage::run(command, &pool).await,
+    }
+}
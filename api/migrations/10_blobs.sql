@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS blobs (
+  hash TEXT,
+  storage_id TEXT,
+  size BIGINT,
+  ref_count BIGINT NOT NULL DEFAULT 0,
+  created_at BIGINT,
+  PRIMARY KEY (hash),
+  UNIQUE (storage_id)
+);
+
+ALTER TABLE files ADD COLUMN hash TEXT;
@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN detected_content_type TEXT;
@@ -0,0 +1,4 @@
+ALTER TABLE files ADD COLUMN tier TEXT NOT NULL DEFAULT 'hot';
+ALTER TABLE files ADD COLUMN last_viewed_at BIGINT;
+
+CREATE INDEX IF NOT EXISTS files_hot_storage_id_idx ON files (storage_id) WHERE tier = 'hot';
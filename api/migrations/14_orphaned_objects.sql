@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS orphaned_objects (
+  storage_id TEXT,
+  tier TEXT,
+  found_at BIGINT NOT NULL,
+  PRIMARY KEY (storage_id, tier)
+);
+
+ALTER TABLE files ADD COLUMN missing_at BIGINT;
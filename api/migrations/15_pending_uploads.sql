@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN pending_until BIGINT;
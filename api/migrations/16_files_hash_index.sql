@@ -0,0 +1 @@
+CREATE INDEX files_hash_idx on files (hash) WHERE hash IS NOT NULL;
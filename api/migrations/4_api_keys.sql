@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS api_keys (
+  id TEXT,
+  name TEXT,
+  key_hash TEXT,
+  created_at BIGINT,
+  revoked_at BIGINT,
+  PRIMARY KEY (id),
+  UNIQUE (name)
+);
+
+CREATE UNIQUE INDEX api_keys_key_hash_idx on api_keys (key_hash);
+
+ALTER TABLE files ADD COLUMN api_key_id TEXT REFERENCES api_keys (id) ON DELETE SET NULL;
+CREATE INDEX files_api_key_id_idx on files (api_key_id);
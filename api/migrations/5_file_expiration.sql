@@ -0,0 +1,2 @@
+ALTER TABLE files ADD COLUMN expires_at BIGINT;
+CREATE INDEX files_expires_at_idx on files (expires_at);
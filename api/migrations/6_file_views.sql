@@ -0,0 +1,2 @@
+ALTER TABLE files ADD COLUMN views BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE files ADD COLUMN max_views BIGINT;
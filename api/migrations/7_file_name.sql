@@ -0,0 +1 @@
+ALTER TABLE files ADD COLUMN file_name TEXT;
@@ -0,0 +1 @@
+CREATE INDEX files_api_key_id_uploaded_at_idx on files (api_key_id, uploaded_at DESC, id DESC);
@@ -0,0 +1,21 @@
+use macros::PostgresRow;
+use sqlx::Row;
+
+/// Stores information about an api key allowed to upload files
+#[derive(Debug, Clone, PostgresRow)]
+pub struct ApiKeyEntity {
+    pub id: String,
+    pub name: String,
+    pub key_hash: String,
+    pub created_at: i64,
+    pub revoked_at: Option<i64>,
+}
+
+/// Stores an api key alongside the amount of files uploaded using it
+#[derive(Debug, Clone, PostgresRow)]
+pub struct ApiKeyUsageEntity {
+    #[postgres(flatten)]
+    pub key: ApiKeyEntity,
+    pub file_count: i64,
+    pub total_size: i64,
+}
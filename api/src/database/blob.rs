@@ -0,0 +1,13 @@
+use macros::PostgresRow;
+use sqlx::Row;
+
+/// Stores information about a stored object which may be shared by multiple files
+/// with the same contents
+#[derive(Debug, Clone, PostgresRow)]
+pub struct BlobEntity {
+    pub hash: String,
+    pub storage_id: String,
+    pub size: i64,
+    pub ref_count: i64,
+    pub created_at: i64,
+}
@@ -1,6 +1,8 @@
 use macros::PostgresRow;
 use sqlx::Row;
 
+use crate::storage::driver::StorageTier;
+
 /// Stores information about an uploaded file
 #[derive(Debug, Clone, PostgresRow)]
 pub struct FileEntity {
@@ -9,4 +11,51 @@ pub struct FileEntity {
     pub secret: String,
     pub uploaded_at: i64,
     pub size: i64,
+    pub api_key_id: Option<String>,
+    pub expires_at: Option<i64>,
+    pub views: i64,
+    pub max_views: Option<i64>,
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub hash: Option<String>,
+    pub detected_content_type: Option<String>,
+    // Key the stored object is encrypted with, wrapped by a master key
+    pub data_key: Option<String>,
+    // Storage tier the object is stored in
+    pub tier: String,
+    pub last_viewed_at: Option<i64>,
+    // Set once the reconciler finds the object of the file missing from storage
+    pub missing_at: Option<i64>,
+    // Set while the contents are uploaded directly to the storage, the reservation is deleted
+    // if the upload isn't completed until then
+    pub pending_until: Option<i64>,
+}
+
+impl FileEntity {
+    /// Checks whether the file has expired and should no longer be served
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time in ms since the unix epoch
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Returns the id of the object clients upload the contents of a direct upload to, it is
+    /// copied to the storage id once the upload is completed
+    pub fn upload_storage_id(&self) -> String {
+        format!("{}.upload", self.storage_id)
+    }
+
+    /// Checks whether the file has been viewed as often as it is allowed to
+    pub fn is_used_up(&self) -> bool {
+        self.max_views
+            .is_some_and(|max_views| self.views >= max_views)
+    }
+
+    /// Returns the storage tier the object of the file is stored in, files stored before
+    /// tiering was introduced are in the hot tier
+    pub fn storage_tier(&self) -> StorageTier {
+        self.tier.parse().unwrap_or_default()
+    }
 }
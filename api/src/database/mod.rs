@@ -1,2 +1,5 @@
+pub mod api_key;
+pub mod blob;
 pub mod file;
+pub mod orphan;
 pub mod query;
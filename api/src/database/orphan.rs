@@ -0,0 +1,11 @@
+use macros::PostgresRow;
+use sqlx::Row;
+
+/// Stores a storage object which isn't used by any file, the object is deleted once it has
+/// been orphaned for longer than the grace period
+#[derive(Debug, Clone, PostgresRow)]
+pub struct OrphanEntity {
+    pub storage_id: String,
+    pub tier: String,
+    pub found_at: i64,
+}
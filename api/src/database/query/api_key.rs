@@ -0,0 +1,74 @@
+use super::{since_epoch_in_ms, DbResult, PgTransaction};
+use crate::database::api_key::{ApiKeyEntity, ApiKeyUsageEntity};
+
+/// Finds an api key which has not been revoked by the hash of its key
+pub async fn find_api_key_by_hash(
+    transaction: &mut PgTransaction<'_>,
+    key_hash: &String,
+) -> DbResult<ApiKeyEntity> {
+    sqlx::query_as::<_, ApiKeyEntity>(
+        r"SELECT * FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL",
+    )
+    .bind(key_hash)
+    .fetch_one(&mut **transaction)
+    .await
+}
+
+/// Inserts an api key into the database
+pub async fn save_api_key(
+    transaction: &mut PgTransaction<'_>,
+    id: &String,
+    name: &String,
+    key_hash: &String,
+) -> DbResult<()> {
+    sqlx::query(r"INSERT INTO api_keys (id, name, key_hash, created_at) VALUES ($1, $2, $3, $4)")
+        .bind(id)
+        .bind(name)
+        .bind(key_hash)
+        .bind(since_epoch_in_ms())
+        .execute(&mut **transaction)
+        .await
+        .map(|_| ())
+}
+
+/// Lists all api keys (including revoked ones) together with their upload usage
+pub async fn list_api_keys(
+    transaction: &mut PgTransaction<'_>,
+) -> DbResult<Vec<ApiKeyUsageEntity>> {
+    sqlx::query_as::<_, ApiKeyUsageEntity>(
+        r"SELECT k.*, COUNT(f.id) AS file_count, COALESCE(SUM(f.size), 0)::BIGINT AS total_size
+        FROM api_keys k LEFT JOIN files f ON f.api_key_id = k.id
+        GROUP BY k.id ORDER BY k.created_at",
+    )
+    .fetch_all(&mut **transaction)
+    .await
+}
+
+/// Revokes an api key by its name, revoked keys can no longer be used for uploading
+pub async fn revoke_api_key_by_name(
+    transaction: &mut PgTransaction<'_>,
+    name: &String,
+) -> DbResult<ApiKeyEntity> {
+    sqlx::query_as::<_, ApiKeyEntity>(
+        r"UPDATE api_keys SET revoked_at = $2 WHERE name = $1 AND revoked_at IS NULL RETURNING *",
+    )
+    .bind(name)
+    .bind(since_epoch_in_ms())
+    .fetch_one(&mut **transaction)
+    .await
+}
+
+/// Replaces the key of an api key by its name, the old key stops working immediately
+pub async fn rotate_api_key_by_name(
+    transaction: &mut PgTransaction<'_>,
+    name: &String,
+    key_hash: &String,
+) -> DbResult<ApiKeyEntity> {
+    sqlx::query_as::<_, ApiKeyEntity>(
+        r"UPDATE api_keys SET key_hash = $2 WHERE name = $1 AND revoked_at IS NULL RETURNING *",
+    )
+    .bind(name)
+    .bind(key_hash)
+    .fetch_one(&mut **transaction)
+    .await
+}
@@ -0,0 +1,68 @@
+use super::{since_epoch_in_ms, DbResult, PgTransaction};
+use crate::database::{blob::BlobEntity, file::FileEntity};
+
+/// Adds a reference to the blob with the given hash, the blob is created using the given
+/// storage id if it does not exist yet. Concurrent uploads of the same contents wait on
+/// each other so only one of them stores the object
+///
+/// # Returns
+///
+/// The blob, its storage id only matches the given one if the object still has to be stored
+pub async fn acquire_blob(
+    transaction: &mut PgTransaction<'_>,
+    hash: &String,
+    storage_id: &String,
+    size: &i64,
+) -> DbResult<BlobEntity> {
+    sqlx::query_as::<_, BlobEntity>(
+        r"INSERT INTO blobs (hash, storage_id, size, ref_count, created_at) VALUES ($1, $2, $3, 1, $4)
+        ON CONFLICT (hash) DO UPDATE SET ref_count = blobs.ref_count + 1 RETURNING *",
+    )
+    .bind(hash)
+    .bind(storage_id)
+    .bind(size)
+    .bind(since_epoch_in_ms())
+    .fetch_one(&mut **transaction)
+    .await
+}
+
+/// Removes the reference of a deleted file from its blob, the blob is deleted once the
+/// last reference is gone
+///
+/// # Returns
+///
+/// Whether the storage object of the file is no longer used and has to be deleted
+pub async fn release_blob(
+    transaction: &mut PgTransaction<'_>,
+    file: &FileEntity,
+) -> DbResult<bool> {
+    let blob = sqlx::query_as::<_, BlobEntity>(
+        r"UPDATE blobs SET ref_count = ref_count - 1 WHERE storage_id = $1 RETURNING *",
+    )
+    .bind(&file.storage_id)
+    .fetch_optional(&mut **transaction)
+    .await?;
+
+    match blob {
+        Some(blob) if blob.ref_count <= 0 => {
+            sqlx::query(r"DELETE FROM blobs WHERE storage_id = $1")
+                .bind(&blob.storage_id)
+                .execute(&mut **transaction)
+                .await?;
+            Ok(true)
+        }
+        Some(_) => Ok(false),
+        // Files uploaded before deduplication own their object
+        None => Ok(true),
+    }
+}
+
+/// Locks the blob of a storage object until the transaction ends, so no files can start or
+/// stop sharing it meanwhile
+pub async fn lock_blob(transaction: &mut PgTransaction<'_>, storage_id: &String) -> DbResult<()> {
+    sqlx::query(r"SELECT 1 FROM blobs WHERE storage_id = $1 FOR UPDATE")
+        .bind(storage_id)
+        .fetch_optional(&mut **transaction)
+        .await
+        .map(|_| ())
+}
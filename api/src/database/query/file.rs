@@ -1,55 +1,446 @@
-use std::time::{SystemTime, UNIX_EPOCH};
-
 use super::{DbResult, PgTransaction};
 use crate::database::file::FileEntity;
 
+/// Filters applied when listing the files of an api key
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    // Only files uploaded before this position (upload time, id) are returned
+    pub cursor: Option<(i64, String)>,
+    // SQL `LIKE` pattern the detected or declared content type has to match
+    pub content_type: Option<String>,
+    // Inclusive lower bound of the upload time
+    pub uploaded_after: Option<i64>,
+    // Exclusive upper bound of the upload time
+    pub uploaded_before: Option<i64>,
+}
+
 /// Finds a file by it's public id
 pub async fn find_file_by_id(
     transaction: &mut PgTransaction<'_>,
     id: &String,
 ) -> DbResult<FileEntity> {
-    sqlx::query_as::<_, FileEntity>(r"SELECT * FROM files WHERE id = $1")
-        .bind(&id)
+    sqlx::query_as::<_, FileEntity>(r"SELECT * FROM files WHERE id = $1 AND pending_until IS NULL")
+        .bind(id)
         .fetch_one(&mut **transaction)
         .await
 }
 
 /// Inserts a file into the database
-pub async fn save_file(
-    transaction: &mut PgTransaction<'_>,
-    id: &String,
-    bucket_id: &String,
-    secret: &String,
-    size: &i64,
-) -> DbResult<()> {
+pub async fn save_file(transaction: &mut PgTransaction<'_>, file: &FileEntity) -> DbResult<()> {
     sqlx::query(
-        r"INSERT INTO files (id, storage_id, secret, uploaded_at, size) VALUES ($1, $2, $3, $4, $5)",
+        r"INSERT INTO files (id, storage_id, secret, uploaded_at, size, api_key_id, expires_at, views, max_views, file_name, content_type, hash, detected_content_type, data_key, tier, last_viewed_at, pending_until)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)",
     )
-    .bind(&id)
-    .bind(&bucket_id)
-    .bind(&secret)
-    .bind(&since_epoch_in_ms())
-    .bind(&size)
+    .bind(&file.id)
+    .bind(&file.storage_id)
+    .bind(&file.secret)
+    .bind(file.uploaded_at)
+    .bind(file.size)
+    .bind(&file.api_key_id)
+    .bind(file.expires_at)
+    .bind(file.views)
+    .bind(file.max_views)
+    .bind(&file.file_name)
+    .bind(&file.content_type)
+    .bind(&file.hash)
+    .bind(&file.detected_content_type)
+    .bind(&file.data_key)
+    .bind(&file.tier)
+    .bind(file.last_viewed_at)
+    .bind(file.pending_until)
     .execute(&mut **transaction)
     .await
     .map(|_| ())
 }
 
+/// Finds a file storing the contents with the given hash, files sharing an object have to
+/// use the same data key and tier
+pub async fn find_file_by_hash(
+    transaction: &mut PgTransaction<'_>,
+    hash: &String,
+) -> DbResult<Option<FileEntity>> {
+    sqlx::query_as::<_, FileEntity>(r"SELECT * FROM files WHERE hash = $1 LIMIT 1")
+        .bind(hash)
+        .fetch_optional(&mut **transaction)
+        .await
+}
+
+/// Counts a view of a file with a view limit, fails if the file has no views left. The row
+/// stays locked until the transaction ends so concurrent views can't exceed the maximum
+///
+/// # Arguments
+///
+/// * `id` - The public id of the file
+/// * `now` - The current time in ms since the unix epoch
+pub async fn increment_file_views(
+    transaction: &mut PgTransaction<'_>,
+    id: &String,
+    now: i64,
+) -> DbResult<FileEntity> {
+    sqlx::query_as::<_, FileEntity>(
+        r"UPDATE files SET views = views + 1, last_viewed_at = $2
+        WHERE id = $1 AND views < max_views RETURNING *",
+    )
+    .bind(id)
+    .bind(now)
+    .fetch_one(&mut **transaction)
+    .await
+}
+
+/// Counts a view of a file without a view limit and remembers when it was last viewed
+///
+/// # Arguments
+///
+/// * `id` - The public id of the file
+/// * `now` - The current time in ms since the unix epoch
+pub async fn record_file_view(
+    transaction: &mut PgTransaction<'_>,
+    id: &String,
+    now: i64,
+) -> DbResult<()> {
+    sqlx::query(r"UPDATE files SET views = views + 1, last_viewed_at = $2 WHERE id = $1")
+        .bind(id)
+        .bind(now)
+        .execute(&mut **transaction)
+        .await
+        .map(|_| ())
+}
+
 /// Deletes a file by it's secret id (given to uploader for deletion)
 pub async fn delete_file_by_secret(
     transaction: &mut PgTransaction<'_>,
     secret: &String,
 ) -> DbResult<FileEntity> {
-    sqlx::query_as::<_, FileEntity>(r"DELETE FROM files WHERE secret = $1 RETURNING *")
-        .bind(&secret)
+    sqlx::query_as::<_, FileEntity>(
+        r"DELETE FROM files WHERE secret = $1 AND pending_until IS NULL RETURNING *",
+    )
+    .bind(secret)
+    .fetch_one(&mut **transaction)
+    .await
+}
+
+/// Deletes a file by it's public id
+pub async fn delete_file_by_id(
+    transaction: &mut PgTransaction<'_>,
+    id: &String,
+) -> DbResult<FileEntity> {
+    sqlx::query_as::<_, FileEntity>(r"DELETE FROM files WHERE id = $1 RETURNING *")
+        .bind(id)
         .fetch_one(&mut **transaction)
         .await
 }
 
-/// Returns the time passed since the unix epoch in ms
-fn since_epoch_in_ms() -> i64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_millis() as i64
+/// Deletes a file if it expired or its upload wasn't completed before the given time,
+/// returns nothing if the file is gone or was activated in the meantime
+///
+/// # Arguments
+///
+/// * `id` - The id of the file
+/// * `now` - The current time in ms since the unix epoch
+pub async fn delete_expired_file(
+    transaction: &mut PgTransaction<'_>,
+    id: &String,
+    now: i64,
+) -> DbResult<Option<FileEntity>> {
+    sqlx::query_as::<_, FileEntity>(
+        r"DELETE FROM files WHERE id = $1 AND (expires_at <= $2 OR pending_until <= $2)
+        RETURNING *",
+    )
+    .bind(id)
+    .bind(now)
+    .fetch_optional(&mut **transaction)
+    .await
+}
+
+/// Finds files which expired or whose upload wasn't completed before the given time
+///
+/// # Arguments
+///
+/// * `now` - The current time in ms since the unix epoch
+/// * `limit` - The maximum amount of files to return
+pub async fn find_expired_files(
+    transaction: &mut PgTransaction<'_>,
+    now: i64,
+    limit: i64,
+) -> DbResult<Vec<FileEntity>> {
+    sqlx::query_as::<_, FileEntity>(
+        r"SELECT * FROM files WHERE expires_at <= $1 OR pending_until <= $1
+        ORDER BY LEAST(expires_at, pending_until) LIMIT $2",
+    )
+    .bind(now)
+    .bind(limit)
+    .fetch_all(&mut **transaction)
+    .await
+}
+
+/// Lists the files uploaded using an api key, newest first
+///
+/// # Arguments
+///
+/// * `api_key_id` - The id of the api key
+/// * `filter` - The filters to apply
+/// * `limit` - The maximum amount of files to return
+pub async fn list_files_by_api_key(
+    transaction: &mut PgTransaction<'_>,
+    api_key_id: &String,
+    filter: &FileFilter,
+    limit: i64,
+) -> DbResult<Vec<FileEntity>> {
+    let (cursor_at, cursor_id) = filter.cursor.clone().unzip();
+    sqlx::query_as::<_, FileEntity>(
+        r"SELECT * FROM files WHERE api_key_id = $1 AND pending_until IS NULL
+        AND ($2::BIGINT IS NULL OR (uploaded_at, id) < ($2, $3))
+        AND ($4::TEXT IS NULL OR COALESCE(detected_content_type, content_type) LIKE $4)
+        AND ($5::BIGINT IS NULL OR uploaded_at >= $5)
+        AND ($6::BIGINT IS NULL OR uploaded_at < $6)
+        ORDER BY uploaded_at DESC, id DESC LIMIT $7",
+    )
+    .bind(api_key_id)
+    .bind(cursor_at)
+    .bind(cursor_id)
+    .bind(&filter.content_type)
+    .bind(filter.uploaded_after)
+    .bind(filter.uploaded_before)
+    .bind(limit)
+    .fetch_all(&mut **transaction)
+    .await
+}
+
+/// Deletes multiple files by their public ids, only files uploaded using the api key are deleted
+pub async fn delete_files_by_ids(
+    transaction: &mut PgTransaction<'_>,
+    ids: &[String],
+    api_key_id: &String,
+) -> DbResult<Vec<FileEntity>> {
+    sqlx::query_as::<_, FileEntity>(
+        r"DELETE FROM files WHERE id = ANY($1) AND api_key_id = $2 AND pending_until IS NULL
+        RETURNING *",
+    )
+    .bind(ids)
+    .bind(api_key_id)
+    .fetch_all(&mut **transaction)
+    .await
+}
+
+/// Finds encrypted files whose data key was not wrapped by the given master key
+///
+/// # Arguments
+///
+/// * `key_id` - The id of the current master key
+/// * `limit` - The maximum amount of files to return
+pub async fn find_files_with_foreign_data_key(
+    transaction: &mut PgTransaction<'_>,
+    key_id: &str,
+    limit: i64,
+) -> DbResult<Vec<FileEntity>> {
+    sqlx::query_as::<_, FileEntity>(
+        r"SELECT * FROM files WHERE data_key IS NOT NULL AND data_key NOT LIKE $1 || ':%'
+        ORDER BY id LIMIT $2",
+    )
+    .bind(key_id)
+    .bind(limit)
+    .fetch_all(&mut **transaction)
+    .await
+}
+
+/// Replaces the wrapped data key of a file
+pub async fn update_file_data_key(
+    transaction: &mut PgTransaction<'_>,
+    id: &String,
+    data_key: &String,
+) -> DbResult<()> {
+    sqlx::query(r"UPDATE files SET data_key = $2 WHERE id = $1")
+        .bind(id)
+        .bind(data_key)
+        .execute(&mut **transaction)
+        .await
+        .map(|_| ())
+}
+
+/// Finds the storage objects in the hot tier which weren't used for a while, objects are
+/// only returned if every file sharing them is unused
+///
+/// # Arguments
+///
+/// * `uploaded_before` - Objects whose newest file was uploaded before this time are returned
+/// * `viewed_before` - Objects whose files were last viewed before this time are returned
+/// * `limit` - The maximum amount of storage ids to return
+pub async fn find_unused_hot_storage_ids(
+    transaction: &mut PgTransaction<'_>,
+    uploaded_before: Option<i64>,
+    viewed_before: Option<i64>,
+    limit: i64,
+) -> DbResult<Vec<String>> {
+    sqlx::query_scalar::<_, String>(
+        r"SELECT storage_id FROM files WHERE tier = 'hot' AND pending_until IS NULL
+        GROUP BY storage_id
+        HAVING ($1::BIGINT IS NOT NULL AND MAX(uploaded_at) <= $1)
+        OR ($2::BIGINT IS NOT NULL AND MAX(COALESCE(last_viewed_at, uploaded_at)) <= $2)
+        ORDER BY MAX(COALESCE(last_viewed_at, uploaded_at)) LIMIT $3",
+    )
+    .bind(uploaded_before)
+    .bind(viewed_before)
+    .bind(limit)
+    .fetch_all(&mut **transaction)
+    .await
+}
+
+/// Moves all files sharing a storage object to another tier
+///
+/// # Returns
+///
+/// The amount of updated files
+pub async fn update_files_tier(
+    transaction: &mut PgTransaction<'_>,
+    storage_id: &String,
+    tier: &str,
+) -> DbResult<u64> {
+    sqlx::query(r"UPDATE files SET tier = $2 WHERE storage_id = $1")
+        .bind(storage_id)
+        .bind(tier)
+        .execute(&mut **transaction)
+        .await
+        .map(|result| result.rows_affected())
+}
+
+/// Lists the storage objects of the files in a tier, ordered by their storage id
+///
+/// # Arguments
+///
+/// * `tier` - The tier of the files, files of all tiers are listed if not present
+/// * `after` - Only storage ids after this one are returned
+/// * `limit` - The maximum amount of storage ids to return
+pub async fn list_storage_ids(
+    transaction: &mut PgTransaction<'_>,
+    tier: Option<&str>,
+    after: Option<&String>,
+    limit: i64,
+) -> DbResult<Vec<String>> {
+    sqlx::query_scalar::<_, String>(
+        r"SELECT DISTINCT storage_id FROM files WHERE ($1::TEXT IS NULL OR tier = $1)
+        AND pending_until IS NULL AND ($2::TEXT IS NULL OR storage_id > $2) ORDER BY storage_id LIMIT $3",
+    )
+    .bind(tier)
+    .bind(after)
+    .bind(limit)
+    .fetch_all(&mut **transaction)
+    .await
+}
+
+/// Lists the objects pending direct uploads are uploaded to, ordered by their id
+///
+/// # Arguments
+///
+/// * `after` - Only objects after this one are returned
+/// * `limit` - The maximum amount of object ids to return
+pub async fn list_pending_upload_ids(
+    transaction: &mut PgTransaction<'_>,
+    after: Option<&String>,
+    limit: i64,
+) -> DbResult<Vec<String>> {
+    sqlx::query_scalar::<_, String>(
+        r"SELECT storage_id || '.upload' AS upload_id FROM files WHERE pending_until IS NOT NULL
+        AND ($1::TEXT IS NULL OR storage_id || '.upload' > $1) ORDER BY upload_id LIMIT $2",
+    )
+    .bind(after)
+    .bind(limit)
+    .fetch_all(&mut **transaction)
+    .await
+}
+
+/// Filters the storage ids which are used by files, the objects direct uploads are uploaded
+/// to are used as long as their file is pending
+///
+/// # Arguments
+///
+/// * `storage_ids` - The storage ids to check
+/// * `tier` - The tier the files have to be in, files of all tiers are checked if not present
+pub async fn find_used_storage_ids(
+    transaction: &mut PgTransaction<'_>,
+    storage_ids: &[String],
+    tier: Option<&str>,
+) -> DbResult<Vec<String>> {
+    sqlx::query_scalar::<_, String>(
+        r"SELECT storage_id FROM files WHERE storage_id = ANY($1)
+        AND ($2::TEXT IS NULL OR tier = $2)
+        UNION
+        SELECT storage_id || '.upload' FROM files WHERE pending_until IS NOT NULL
+        AND storage_id = ANY(SELECT LEFT(id, -7) FROM UNNEST($1::TEXT[]) AS id WHERE id LIKE '%.upload')
+        AND ($2::TEXT IS NULL OR tier = $2)",
+    )
+    .bind(storage_ids)
+    .bind(tier)
+    .fetch_all(&mut **transaction)
+    .await
+}
+
+/// Flags the files of a storage object which is missing from the storage driver, files which
+/// were flagged before keep the time they were first found missing at
+pub async fn mark_files_missing(
+    transaction: &mut PgTransaction<'_>,
+    storage_id: &String,
+    now: i64,
+) -> DbResult<()> {
+    sqlx::query(r"UPDATE files SET missing_at = COALESCE(missing_at, $2) WHERE storage_id = $1")
+        .bind(storage_id)
+        .bind(now)
+        .execute(&mut **transaction)
+        .await
+        .map(|_| ())
+}
+
+/// Removes the missing flag from the files of storage objects which were found again
+pub async fn clear_files_missing(
+    transaction: &mut PgTransaction<'_>,
+    storage_ids: &[String],
+) -> DbResult<()> {
+    sqlx::query(
+        r"UPDATE files SET missing_at = NULL WHERE storage_id = ANY($1) AND missing_at IS NOT NULL",
+    )
+    .bind(storage_ids)
+    .execute(&mut **transaction)
+    .await
+    .map(|_| ())
+}
+
+/// Finds a file whose contents are still being uploaded and locks it until the transaction
+/// ends, only files reserved using the api key are returned
+pub async fn find_pending_file(
+    transaction: &mut PgTransaction<'_>,
+    id: &String,
+    api_key_id: &String,
+) -> DbResult<FileEntity> {
+    sqlx::query_as::<_, FileEntity>(
+        r"SELECT * FROM files WHERE id = $1 AND api_key_id = $2 AND pending_until IS NOT NULL
+        FOR UPDATE",
+    )
+    .bind(id)
+    .bind(api_key_id)
+    .fetch_one(&mut **transaction)
+    .await
+}
+
+/// Makes a file whose upload was completed available, its expiration starts once it is
+/// activated
+///
+/// # Arguments
+///
+/// * `id` - The public id of the file
+/// * `detected_content_type` - The content type detected from the uploaded contents
+/// * `uploaded_at` - The time the upload was completed at in ms since the unix epoch
+pub async fn activate_file(
+    transaction: &mut PgTransaction<'_>,
+    id: &String,
+    detected_content_type: Option<&str>,
+    uploaded_at: i64,
+) -> DbResult<FileEntity> {
+    sqlx::query_as::<_, FileEntity>(
+        r"UPDATE files SET pending_until = NULL, detected_content_type = $2, uploaded_at = $3,
+        expires_at = expires_at + ($3 - uploaded_at) WHERE id = $1 RETURNING *",
+    )
+    .bind(id)
+    .bind(detected_content_type)
+    .bind(uploaded_at)
+    .fetch_one(&mut **transaction)
+    .await
 }
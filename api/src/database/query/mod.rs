@@ -1,4 +1,17 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub mod api_key;
+pub mod blob;
 pub mod file;
+pub mod orphan;
 
 pub type PgTransaction<'a> = sqlx::Transaction<'a, sqlx::Postgres>;
 pub type DbResult<T> = std::result::Result<T, sqlx::Error>;
+
+/// Returns the time passed since the unix epoch in ms
+pub(crate) fn since_epoch_in_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as i64
+}
@@ -0,0 +1,51 @@
+use super::{DbResult, PgTransaction};
+use crate::database::orphan::OrphanEntity;
+
+/// Records storage objects as orphaned, objects which were recorded before keep the time they
+/// were first found at. Records of objects which are no longer orphaned are removed
+///
+/// # Arguments
+///
+/// * `tier` - The tier the objects are stored in
+/// * `storage_ids` - All orphaned objects of the tier
+/// * `now` - The current time in ms since the unix epoch
+///
+/// # Returns
+///
+/// The records of all orphaned objects of the tier
+pub async fn record_orphans(
+    transaction: &mut PgTransaction<'_>,
+    tier: &str,
+    storage_ids: &[String],
+    now: i64,
+) -> DbResult<Vec<OrphanEntity>> {
+    sqlx::query(r"DELETE FROM orphaned_objects WHERE tier = $1 AND NOT (storage_id = ANY($2))")
+        .bind(tier)
+        .bind(storage_ids)
+        .execute(&mut **transaction)
+        .await?;
+    sqlx::query_as::<_, OrphanEntity>(
+        r"INSERT INTO orphaned_objects (storage_id, tier, found_at)
+        SELECT storage_id, $1, $3 FROM UNNEST($2::TEXT[]) AS storage_id
+        ON CONFLICT (storage_id, tier) DO UPDATE SET found_at = orphaned_objects.found_at
+        RETURNING *",
+    )
+    .bind(tier)
+    .bind(storage_ids)
+    .bind(now)
+    .fetch_all(&mut **transaction)
+    .await
+}
+
+/// Removes the record of an orphaned object after it has been deleted
+pub async fn delete_orphan(
+    transaction: &mut PgTransaction<'_>,
+    orphan: &OrphanEntity,
+) -> DbResult<()> {
+    sqlx::query(r"DELETE FROM orphaned_objects WHERE storage_id = $1 AND tier = $2")
+        .bind(&orphan.storage_id)
+        .bind(&orphan.tier)
+        .execute(&mut **transaction)
+        .await
+        .map(|_| ())
+}
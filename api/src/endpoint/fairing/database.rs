@@ -32,12 +32,33 @@ impl Deref for PostgresDb {
     }
 }
 
+impl Default for PostgresFairing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PostgresFairing {
     pub fn new() -> Self {
         Self {}
     }
 }
 
+/// Connects to the postgres database and applies all pending migrations
+///
+/// # Arguments
+///
+/// * `config` - The database config
+///
+/// # Returns
+///
+/// The connection pool
+pub async fn connect(config: &PostgresConfig) -> Result<PostgresPool, sqlx::Error> {
+    let pool = sqlx::PgPool::connect(&config.url).await?;
+    sqlx::migrate!("./migrations").run(&pool).await?;
+    Ok(pool)
+}
+
 #[rocket::async_trait]
 impl Fairing for PostgresFairing {
     fn info(&self) -> Info {
@@ -49,11 +70,8 @@ impl Fairing for PostgresFairing {
 
     async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
         let config: PostgresConfig = rocket.figment().focus("database").extract().unwrap();
-        match sqlx::PgPool::connect(&config.url).await {
-            Ok(pool) => {
-                sqlx::migrate!("./migrations").run(&pool).await.unwrap();
-                Ok(rocket.manage(pool))
-            }
+        match connect(&config).await {
+            Ok(pool) => Ok(rocket.manage(pool)),
             Err(err) => {
                 error!("Failed to initialize postgres database client: {}", err);
                 Err(rocket)
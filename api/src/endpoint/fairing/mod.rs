@@ -1,2 +1,6 @@
 pub mod database;
+pub mod reaper;
+pub mod reconciler;
+pub mod repair;
 pub mod storage;
+pub mod tiering;
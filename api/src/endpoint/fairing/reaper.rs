@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use log::{error, info};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio::{self, time},
+    Orbit, Rocket,
+};
+
+use crate::{
+    database::query::{
+        blob::release_blob,
+        file::{delete_expired_file, find_expired_files},
+        since_epoch_in_ms,
+    },
+    storage::driver::StorageDriver,
+    GlobalConfig,
+};
+
+use super::database::PostgresPool;
+
+/// Default interval in which expired files are deleted, time is in seconds
+const DEFAULT_REAPER_INTERVAL: u64 = 60;
+/// Amount of expired files deleted per batch
+const REAPER_BATCH_SIZE: i64 = 100;
+
+/// Periodically deletes expired files from the database and storage driver
+pub struct FileReaperFairing;
+
+impl Default for FileReaperFairing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileReaperFairing {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for FileReaperFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "File Reaper Fairing",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let (Some(pool), Some(storage)) = (
+            rocket.state::<PostgresPool>(),
+            rocket.state::<StorageDriver>(),
+        ) else {
+            error!("Unable to start file reaper, database or storage driver are unavailable");
+            return;
+        };
+        let interval = rocket
+            .state::<GlobalConfig>()
+            .and_then(|config| config.reaper_interval)
+            .unwrap_or(DEFAULT_REAPER_INTERVAL);
+
+        let pool = pool.clone();
+        let storage = storage.clone();
+        let mut shutdown = rocket.shutdown();
+        tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(interval));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => reap_expired_files(&pool, &storage).await,
+                    _ = &mut shutdown => break,
+                }
+            }
+        });
+    }
+}
+
+/// Deletes all files which have expired, files are deleted one by one so a storage
+/// failure only keeps the affected file around until the next run
+async fn reap_expired_files(pool: &PostgresPool, storage: &StorageDriver) {
+    loop {
+        let expired = match pool.begin().await {
+            Ok(mut transaction) => {
+                find_expired_files(&mut transaction, since_epoch_in_ms(), REAPER_BATCH_SIZE).await
+            }
+            Err(err) => Err(err),
+        };
+        let expired = match expired {
+            Ok(expired) => expired,
+            Err(err) => {
+                error!("Failed to query expired files: {}", err);
+                return;
+            }
+        };
+
+        let mut deleted = 0;
+        for file in &expired {
+            match reap_file(pool, storage, &file.id).await {
+                Ok(true) => deleted += 1,
+                Ok(false) => {}
+                Err(err) => error!("Failed to delete expired file {}: {}", file.id, err),
+            }
+        }
+        if deleted > 0 {
+            info!("Deleted {} expired files", deleted);
+        }
+        // Stop once all expired files are gone or if none of the batch could be deleted
+        if (expired.len() as i64) < REAPER_BATCH_SIZE || deleted == 0 {
+            return;
+        }
+    }
+}
+
+/// Deletes a single file, the row is only removed if the storage object was deleted
+/// or is still used by other files. Files which were deleted or activated since they
+/// were found are skipped
+async fn reap_file(
+    pool: &PostgresPool,
+    storage: &StorageDriver,
+    id: &String,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut transaction = pool.begin().await?;
+    let Some(file) = delete_expired_file(&mut transaction, id, since_epoch_in_ms()).await? else {
+        return Ok(false);
+    };
+    let storage = storage.tier(file.storage_tier());
+    match file.pending_until {
+        None => {
+            if release_blob(&mut transaction, &file).await? {
+                storage.delete_file(&file.storage_id).await?;
+            }
+        }
+        // Expired reservations have no object of their own yet, but may have left the object
+        // uploaded by the client behind
+        Some(_) => {
+            let upload_id = file.upload_storage_id();
+            if storage.file_exists(&upload_id).await? {
+                storage.delete_file(&upload_id).await?;
+            }
+        }
+    }
+    transaction.commit().await?;
+    Ok(true)
+}
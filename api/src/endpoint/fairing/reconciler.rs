@@ -0,0 +1,293 @@
+use std::{collections::HashSet, time::Duration};
+
+use log::{error, info, warn};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio::{self, time},
+    Orbit, Rocket,
+};
+
+use crate::{
+    database::{
+        orphan::OrphanEntity,
+        query::{
+            file::{
+                clear_files_missing, find_used_storage_ids, list_storage_ids, mark_files_missing,
+            },
+            orphan::{delete_orphan, record_orphans},
+            since_epoch_in_ms,
+        },
+    },
+    storage::driver::{StorageDriver, StorageTier},
+    GlobalConfig,
+};
+
+use super::database::PostgresPool;
+
+/// Default interval in which storage and database are compared, time is in seconds
+const DEFAULT_RECONCILE_INTERVAL: u64 = 86400;
+/// Default time orphaned objects are kept before they are deleted, time is in seconds.
+/// Uploads store their object before the file is committed, so it has to cover the
+/// longest upload
+pub const DEFAULT_ORPHAN_GRACE_PERIOD: u64 = 86400;
+/// Amount of storage ids checked per query
+const RECONCILE_BATCH_SIZE: usize = 1000;
+
+pub type ReconcileResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Periodically reports storage objects which aren't used by any file and flags files whose
+/// objects are missing, the objects are only deleted if `delete_orphans` is enabled
+pub struct StorageReconcilerFairing;
+
+/// Outcome of comparing the storage driver with the database
+#[derive(Debug, Clone, Default)]
+pub struct ReconcileReport {
+    // Objects which aren't used by any file, including the deleted ones
+    pub orphans: Vec<OrphanEntity>,
+    // Amount of orphaned objects which were deleted
+    pub deleted: usize,
+    // Storage ids of files whose objects are missing
+    pub missing: Vec<String>,
+}
+
+impl Default for StorageReconcilerFairing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StorageReconcilerFairing {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for StorageReconcilerFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Storage Reconciler Fairing",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let (Some(pool), Some(storage)) = (
+            rocket.state::<PostgresPool>(),
+            rocket.state::<StorageDriver>(),
+        ) else {
+            error!(
+                "Unable to start storage reconciler, database or storage driver are unavailable"
+            );
+            return;
+        };
+        let config = rocket.state::<GlobalConfig>();
+        let interval = config
+            .and_then(|config| config.reconcile_interval)
+            .unwrap_or(DEFAULT_RECONCILE_INTERVAL);
+        let grace_period = config
+            .and_then(|config| config.orphan_grace_period)
+            .unwrap_or(DEFAULT_ORPHAN_GRACE_PERIOD);
+        let delete = config
+            .and_then(|config| config.delete_orphans)
+            .unwrap_or(false);
+
+        let pool = pool.clone();
+        let storage = storage.clone();
+        let mut shutdown = rocket.shutdown();
+        tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(interval));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        match reconcile_storage(&pool, &storage, grace_period, delete).await {
+                            Ok(report) => log_report(&report),
+                            Err(err) => error!("Failed to reconcile storage: {}", err),
+                        }
+                    }
+                    _ = &mut shutdown => break,
+                }
+            }
+        });
+    }
+}
+
+fn log_report(report: &ReconcileReport) {
+    if report.orphans.len() > report.deleted {
+        info!(
+            "Found {} orphaned storage objects which weren't deleted",
+            report.orphans.len() - report.deleted
+        );
+    }
+    if report.deleted > 0 {
+        info!("Deleted {} orphaned storage objects", report.deleted);
+    }
+    if !report.missing.is_empty() {
+        warn!(
+            "Objects of {} storage ids are missing: {}",
+            report.missing.len(),
+            report.missing.join(", ")
+        );
+    }
+}
+
+/// Compares the objects of every tier with the files in the database. Objects which aren't
+/// used by any file are recorded as orphans, files whose objects are missing are flagged
+///
+/// # Arguments
+///
+/// * `grace_period` - Time in seconds objects have to be orphaned before they are deleted
+/// * `delete` - Whether orphans older than the grace period are deleted
+pub async fn reconcile_storage(
+    pool: &PostgresPool,
+    storage: &StorageDriver,
+    grace_period: u64,
+    delete: bool,
+) -> ReconcileResult<ReconcileReport> {
+    let mut tiers = vec![StorageTier::Hot];
+    if storage.has_cold_tier() {
+        tiers.push(StorageTier::Cold);
+    }
+
+    let mut report = ReconcileReport::default();
+    for tier in tiers {
+        let driver = storage.tier(tier);
+        // Without a cold tier every file is stored in the same backend
+        let tier_filter = storage.has_cold_tier().then(|| tier.as_str());
+        let objects: HashSet<String> = driver.list_files().await?.into_iter().collect();
+
+        let orphans = find_orphans(pool, &objects, tier, tier_filter).await?;
+        let expired = since_epoch_in_ms() - grace_period as i64 * 1000;
+        for orphan in &orphans {
+            if !delete || orphan.found_at > expired {
+                continue;
+            }
+            match delete_orphaned_object(pool, &driver, orphan, tier_filter).await {
+                Ok(true) => report.deleted += 1,
+                Ok(false) => {}
+                Err(err) => error!(
+                    "Failed to delete orphaned storage object {}: {}",
+                    orphan.storage_id, err
+                ),
+            }
+        }
+        report.orphans.extend(orphans);
+        report
+            .missing
+            .extend(flag_missing_files(pool, &driver, &objects, tier_filter).await?);
+    }
+    Ok(report)
+}
+
+/// Records the objects of a tier which aren't used by any file
+async fn find_orphans(
+    pool: &PostgresPool,
+    objects: &HashSet<String>,
+    tier: StorageTier,
+    tier_filter: Option<&str>,
+) -> ReconcileResult<Vec<OrphanEntity>> {
+    let objects: Vec<String> = objects.iter().cloned().collect();
+    let mut orphans = Vec::new();
+    let mut transaction = pool.begin().await?;
+    for batch in objects.chunks(RECONCILE_BATCH_SIZE) {
+        let used: HashSet<String> = find_used_storage_ids(&mut transaction, batch, tier_filter)
+            .await?
+            .into_iter()
+            .collect();
+        orphans.extend(batch.iter().filter(|id| !used.contains(*id)).cloned());
+    }
+    let orphans = record_orphans(
+        &mut transaction,
+        tier.as_str(),
+        &orphans,
+        since_epoch_in_ms(),
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(orphans)
+}
+
+/// Deletes an orphaned object if it still isn't used by any file
+///
+/// # Returns
+///
+/// Whether the object was deleted
+async fn delete_orphaned_object(
+    pool: &PostgresPool,
+    driver: &StorageDriver,
+    orphan: &OrphanEntity,
+    tier: Option<&str>,
+) -> ReconcileResult<bool> {
+    let mut transaction = pool.begin().await?;
+    let used = find_used_storage_ids(
+        &mut transaction,
+        std::slice::from_ref(&orphan.storage_id),
+        tier,
+    )
+    .await?;
+    transaction.commit().await?;
+    if !used.is_empty() {
+        return Ok(false);
+    }
+
+    // The storage is accessed outside of transactions, so no connection is held while
+    // waiting for it
+    driver.delete_file(&orphan.storage_id).await?;
+    let mut transaction = pool.begin().await?;
+    delete_orphan(&mut transaction, orphan).await?;
+    transaction.commit().await?;
+    Ok(true)
+}
+
+/// Flags the files of a tier whose objects are missing and removes the flag from files whose
+/// objects were found again
+///
+/// # Returns
+///
+/// The storage ids of the missing objects
+async fn flag_missing_files(
+    pool: &PostgresPool,
+    driver: &StorageDriver,
+    objects: &HashSet<String>,
+    tier: Option<&str>,
+) -> ReconcileResult<Vec<String>> {
+    let mut missing = Vec::new();
+    let mut after = None;
+    loop {
+        let mut transaction = pool.begin().await?;
+        let storage_ids = list_storage_ids(
+            &mut transaction,
+            tier,
+            after.as_ref(),
+            RECONCILE_BATCH_SIZE as i64,
+        )
+        .await?;
+        transaction.commit().await?;
+        let Some(last) = storage_ids.last().cloned() else {
+            break;
+        };
+
+        // The storage is checked outside of transactions, so no locks are held while waiting
+        // for it
+        let (mut present, absent): (Vec<String>, Vec<String>) =
+            storage_ids.into_iter().partition(|id| objects.contains(id));
+        let mut batch_missing = Vec::new();
+        for storage_id in absent {
+            // The object may have been stored after the objects were listed
+            match driver.file_exists(&storage_id).await? {
+                true => present.push(storage_id),
+                false => batch_missing.push(storage_id),
+            }
+        }
+
+        let mut transaction = pool.begin().await?;
+        clear_files_missing(&mut transaction, &present).await?;
+        for storage_id in &batch_missing {
+            mark_files_missing(&mut transaction, storage_id, since_epoch_in_ms()).await?;
+        }
+        transaction.commit().await?;
+        missing.extend(batch_missing);
+        after = Some(last);
+    }
+    Ok(missing)
+}
@@ -0,0 +1,141 @@
+use std::{collections::HashSet, time::Duration};
+
+use log::{error, info};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio::{self, time},
+    Orbit, Rocket,
+};
+
+use crate::{
+    database::query::file::list_storage_ids,
+    storage::driver::{StorageDriver, StorageTier},
+    GlobalConfig,
+};
+
+use super::{database::PostgresPool, reconciler::ReconcileResult};
+
+/// Default interval in which missing copies of files are restored, time is in seconds
+const DEFAULT_REPAIR_INTERVAL: u64 = 3600;
+/// Amount of storage ids loaded per query
+const REPAIR_BATCH_SIZE: i64 = 1000;
+
+/// Periodically restores copies of files missing from replicas of the storage driver
+pub struct StorageRepairFairing;
+
+impl Default for StorageRepairFairing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StorageRepairFairing {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for StorageRepairFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Storage Repair Fairing",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        // Only replicated storage drivers have copies which can go missing
+        let Some(storage) = rocket
+            .state::<StorageDriver>()
+            .filter(|storage| replicated_tiers(storage).next().is_some())
+        else {
+            return;
+        };
+        let Some(pool) = rocket.state::<PostgresPool>() else {
+            error!("Unable to start storage repair, database is unavailable");
+            return;
+        };
+        let interval = rocket
+            .state::<GlobalConfig>()
+            .and_then(|config| config.repair_interval)
+            .unwrap_or(DEFAULT_REPAIR_INTERVAL);
+
+        let pool = pool.clone();
+        let storage = storage.clone();
+        let mut shutdown = rocket.shutdown();
+        tokio::spawn(async move {
+            // Copies are only missing after replicas failed, so there's nothing to do on startup
+            let period = Duration::from_secs(interval);
+            let mut interval = time::interval_at(time::Instant::now() + period, period);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => repair_storage(&pool, &storage).await,
+                    _ = &mut shutdown => break,
+                }
+            }
+        });
+    }
+}
+
+/// Returns the tiers of the storage driver which store multiple copies of every file
+fn replicated_tiers(storage: &StorageDriver) -> impl Iterator<Item = StorageTier> + '_ {
+    let mut tiers = vec![StorageTier::Hot];
+    if storage.has_cold_tier() {
+        tiers.push(StorageTier::Cold);
+    }
+    tiers
+        .into_iter()
+        .filter(|tier| storage.tier(*tier).has_replicas())
+}
+
+/// Restores missing copies of the files of every replicated tier and logs the outcome, only
+/// objects of files in the database are restored
+async fn repair_storage(pool: &PostgresPool, storage: &StorageDriver) {
+    let mut repaired = 0;
+    for tier in replicated_tiers(storage) {
+        // Without a cold tier every file is stored in the same backend
+        let tier_filter = storage.has_cold_tier().then(|| tier.as_str());
+        let storage_ids = match load_storage_ids(pool, tier_filter).await {
+            Ok(storage_ids) => storage_ids,
+            Err(err) => {
+                error!("Failed to load storage ids of the {} tier: {}", tier, err);
+                continue;
+            }
+        };
+        match storage.tier(tier).repair(&storage_ids).await {
+            Ok(count) => repaired += count,
+            Err(err) => error!(
+                "Failed to repair the {} tier of the storage driver: {}",
+                tier, err
+            ),
+        }
+    }
+    if repaired > 0 {
+        info!("Restored {} missing copies of files", repaired);
+    }
+}
+
+/// Loads the storage ids of all files in a tier
+///
+/// # Arguments
+///
+/// * `tier` - The tier of the files, files of all tiers are loaded if not present
+async fn load_storage_ids(
+    pool: &PostgresPool,
+    tier: Option<&str>,
+) -> ReconcileResult<HashSet<String>> {
+    let mut storage_ids = HashSet::new();
+    let mut after = None;
+    loop {
+        let mut transaction = pool.begin().await?;
+        let batch =
+            list_storage_ids(&mut transaction, tier, after.as_ref(), REPAIR_BATCH_SIZE).await?;
+        transaction.commit().await?;
+        let Some(last) = batch.last().cloned() else {
+            return Ok(storage_ids);
+        };
+        storage_ids.extend(batch);
+        after = Some(last);
+    }
+}
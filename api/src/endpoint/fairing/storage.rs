@@ -1,47 +1,358 @@
-use std::{ops::Deref, path::Path};
+use std::{
+    borrow::Cow, collections::HashMap, error::Error, fmt, ops::Deref, path::Path, sync::Arc,
+    time::Duration,
+};
 
 use aws_credential_types::Credentials;
+use log::error;
 use rocket::{
+    data::ByteUnit,
     fairing::{self, Fairing, Info, Kind},
+    figment::Figment,
     request::{FromRequest, Outcome},
     Build, Request, Rocket,
 };
 use serde::Deserialize;
 
 use crate::{
-    s3::{bucket::Bucket, credentials::BucketCredentials},
-    storage::driver::StorageDriver,
+    s3::{
+        bucket::{Bucket, BucketAddressing},
+        credentials::BucketCredentials,
+        encryption::{BucketEncryption, CustomerKey},
+    },
+    storage::{
+        backend::StorageBackend,
+        drive::{DriveBackend, DriveLayout, DEFAULT_SHARD_DEPTH},
+        driver::StorageDriver,
+        encryption::{EncryptionConfig, Keyring},
+        memory::MemoryBackend,
+        migrating::MigratingBackend,
+        object_storage::{MultipartConfig, ObjectStorageBackend},
+        replicated::{Replica, ReplicatedBackend, WritePolicy},
+    },
 };
 
+/// Time presigned download urls are valid for if not configured, time is in seconds
+const DEFAULT_PRESIGNED_DOWNLOAD_EXPIRATION: u64 = 300;
+/// Capacity of the memory storage if not configured
+const DEFAULT_MEMORY_SIZE: ByteUnit = ByteUnit::Mebibyte(256);
+
 // Provides access to the selected storage driver
 pub struct StorageDriverGuard(pub StorageDriver);
 
-pub struct StorageDriverFairing;
+pub struct StorageDriverFairing {
+    registry: StorageBackendRegistry,
+}
 
-#[derive(Debug, Clone, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub enum StorageDriverType {
-    ObjectStorage,
-    Drive,
+/// Name of a storage backend, selected using `storage_type`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(transparent)]
+pub struct StorageDriverType(Cow<'static, str>);
+
+/// Result of creating a storage backend
+pub type StorageBackendResult = Result<Arc<dyn StorageBackend>, Box<dyn Error + Send + Sync>>;
+
+/// Creates a storage backend, receives the entire config so backends can read their own section
+/// and the registry so backends can be composed of other backends
+pub type StorageBackendFactory =
+    Box<dyn Fn(&Figment, &StorageBackendRegistry) -> StorageBackendResult + Send + Sync>;
+
+/// Maps storage driver types to the factories creating their backends, backends defined in
+/// other crates are added using `register`
+pub struct StorageBackendRegistry {
+    factories: HashMap<StorageDriverType, StorageBackendFactory>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct StorageDriverFairingConfig {
     storage_type: StorageDriverType,
+    // Storage type unused files are moved to, files are only stored in `storage_type` if absent
+    cold_storage_type: Option<StorageDriverType>,
+    // Storage type files are moved away from, files missing in `storage_type` are read from it
+    migrate_from: Option<StorageDriverType>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ObjectStorageConfig {
-    url: String,
+    // Endpoint of the provider, resolved from the region for AWS if absent or empty
+    url: Option<String>,
     name: String,
-    access_key: String,
-    access_key_secret: String,
+    // Static credentials, loaded like the aws cli does (environment, profile files, web
+    // identity token, container or instance metadata) if absent or empty
+    access_key: Option<String>,
+    access_key_secret: Option<String>,
     region: Option<String>,
+    // Passes the bucket name in the path instead of the host, required by most s3 compatible
+    // providers and thus enabled by default
+    path_style: Option<bool>,
+    // Prepended to the keys of all objects, lets multiple instances share a bucket
+    key_prefix: Option<String>,
+    // Storage class new objects are stored in (e.g. `STANDARD_IA`)
+    storage_class: Option<String>,
+    // Encryption applied by the provider to new objects
+    server_side_encryption: Option<ServerSideEncryptionConfig>,
+    // Files larger than this are uploaded in multiple parts
+    multipart_threshold: Option<ByteUnit>,
+    multipart_part_size: Option<ByteUnit>,
+    // Maximum amount of parts uploaded at once
+    multipart_concurrency: Option<usize>,
+    // Redirects downloads to presigned urls of the bucket instead of proxying them
+    presigned_downloads: Option<bool>,
+    // Time presigned download urls are valid for, time is in seconds
+    presigned_download_expiration: Option<u64>,
+    // Lets clients upload files directly to the bucket using presigned urls
+    presigned_uploads: Option<bool>,
+}
+
+/// Server-side encryption of object storage buckets
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerSideEncryptionConfig {
+    // Keys managed by the provider (SSE-S3)
+    S3,
+    // Keys managed by a key management service (SSE-KMS), uses the bucket's default key if
+    // no key id is set
+    Kms { key_id: Option<String> },
+    // Base64 encoded 256 bit key sent with every request (SSE-C), presigned urls can't be
+    // used with it
+    Customer { key: String },
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct DriveStorageConfig {
     path: String,
+    // Amount of subdirectory levels files are spread across, 0 stores all files in `path`
+    shard_depth: Option<usize>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MemoryStorageConfig {
+    // Maximum amount of bytes kept in memory
+    max_size: Option<ByteUnit>,
+    // Evicts the least recently used files once the maximum is reached instead of refusing
+    // new files, evicted files can no longer be served
+    evict: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReplicatedStorageConfig {
+    // Storage types every file is stored in, the first one is the primary
+    replicas: Vec<StorageDriverType>,
+    // Decides when saving a file is successful
+    write_policy: Option<WritePolicy>,
+}
+
+impl ObjectStorageConfig {
+    /// Creates the configured bucket access
+    pub fn bucket(&self) -> Result<Bucket, Box<dyn Error + Send + Sync>> {
+        // Empty values are treated as unset, so blank samples don't select empty credentials
+        let set = |value: &Option<String>| value.clone().filter(|value| !value.is_empty());
+        let credentials = match (set(&self.access_key), set(&self.access_key_secret)) {
+            (Some(access_key), Some(access_key_secret)) => BucketCredentials::new(
+                Credentials::from_keys(access_key, access_key_secret, None),
+                self.region.clone(),
+            ),
+            (None, None) => BucketCredentials::from_provider_chain(self.region.clone()),
+            _ => return Err("access_key and access_key_secret have to be set together".into()),
+        };
+        let addressing = match self.path_style.unwrap_or(true) {
+            true => BucketAddressing::Path,
+            false => BucketAddressing::VirtualHost,
+        };
+
+        let mut bucket = Bucket::new(self.name.clone(), set(&self.url), credentials, addressing);
+        if let Some(key_prefix) = &self.key_prefix {
+            bucket = bucket.with_key_prefix(key_prefix);
+        }
+        if let Some(storage_class) = &self.storage_class {
+            bucket = bucket.with_storage_class(storage_class);
+        }
+        if let Some(encryption) = &self.server_side_encryption {
+            bucket = bucket.with_encryption(match encryption {
+                ServerSideEncryptionConfig::S3 => BucketEncryption::S3,
+                ServerSideEncryptionConfig::Kms { key_id } => BucketEncryption::Kms(key_id.clone()),
+                ServerSideEncryptionConfig::Customer { key } => {
+                    BucketEncryption::Customer(CustomerKey::from_base64(key)?)
+                }
+            });
+        }
+        Ok(bucket)
+    }
+
+    /// Creates the multipart config, unset values use the defaults
+    pub fn multipart(&self) -> MultipartConfig {
+        let default = MultipartConfig::default();
+        MultipartConfig {
+            threshold: self
+                .multipart_threshold
+                .map_or(default.threshold, |size| size.as_u64()),
+            part_size: self
+                .multipart_part_size
+                .map_or(default.part_size, |size| size.as_u64()),
+            concurrency: self.multipart_concurrency.unwrap_or(default.concurrency),
+        }
+    }
+}
+
+impl DriveStorageConfig {
+    /// Creates the layout of the configured storage directory
+    pub fn layout(&self) -> DriveLayout {
+        DriveLayout::new(
+            Path::new(self.path.as_str()).to_path_buf(),
+            self.shard_depth.unwrap_or(DEFAULT_SHARD_DEPTH),
+        )
+    }
+}
+
+impl StorageDriverType {
+    pub const OBJECT_STORAGE: Self = Self(Cow::Borrowed("object_storage"));
+    pub const DRIVE: Self = Self(Cow::Borrowed("drive"));
+    pub const MEMORY: Self = Self(Cow::Borrowed("memory"));
+    pub const REPLICATED: Self = Self(Cow::Borrowed("replicated"));
+
+    pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
+        Self(name.into())
+    }
+}
+
+impl fmt::Display for StorageDriverType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Default for StorageBackendRegistry {
+    /// Creates a registry containing the backends shipped with the api
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register(StorageDriverType::OBJECT_STORAGE, |figment, _| {
+            let config: ObjectStorageConfig = figment.focus("storage.object").extract()?;
+            let mut backend =
+                ObjectStorageBackend::new(config.bucket()?).with_multipart(config.multipart());
+            if config.presigned_downloads.unwrap_or(false) {
+                backend = backend.with_presigned_downloads(Duration::from_secs(
+                    config
+                        .presigned_download_expiration
+                        .unwrap_or(DEFAULT_PRESIGNED_DOWNLOAD_EXPIRATION),
+                ));
+            }
+            if config.presigned_uploads.unwrap_or(false) {
+                backend = backend.with_presigned_uploads();
+            }
+            Ok(Arc::new(backend))
+        });
+        registry.register(StorageDriverType::DRIVE, |figment, _| {
+            let config: DriveStorageConfig = figment.focus("storage.drive").extract()?;
+            Ok(Arc::new(DriveBackend::new(config.layout())))
+        });
+        registry.register(StorageDriverType::MEMORY, |figment, _| {
+            let config: MemoryStorageConfig = figment.focus("storage.memory").extract()?;
+            Ok(Arc::new(MemoryBackend::new(
+                config.max_size.unwrap_or(DEFAULT_MEMORY_SIZE).as_u64(),
+                config.evict.unwrap_or(false),
+            )))
+        });
+        registry.register(StorageDriverType::REPLICATED, |figment, registry| {
+            let config: ReplicatedStorageConfig = figment.focus("storage.replicated").extract()?;
+            let mut replicas = Vec::with_capacity(config.replicas.len());
+            for driver_type in config.replicas {
+                if driver_type == StorageDriverType::REPLICATED {
+                    return Err("Replicated storage can't contain itself".into());
+                }
+                let backend = registry
+                    .create(&driver_type, figment)
+                    .ok_or_else(|| format!("Unknown storage type {}", driver_type))??;
+                replicas.push(Replica::new(driver_type.to_string(), backend));
+            }
+            if replicas.is_empty() {
+                return Err("Replicated storage requires at least one replica".into());
+            }
+            Ok(Arc::new(ReplicatedBackend::new(
+                replicas,
+                config.write_policy.unwrap_or_default(),
+            )))
+        });
+        registry
+    }
+}
+
+impl StorageBackendRegistry {
+    /// Creates a registry without any backends
+    pub fn new() -> Self {
+        Self {
+            factories: HashMap::new(),
+        }
+    }
+
+    /// Registers a backend, replaces the backend previously registered for the type
+    ///
+    /// # Arguments
+    ///
+    /// * `driver_type` - The value of `storage_type` selecting the backend
+    /// * `factory` - Creates the backend from the config
+    pub fn register<F>(&mut self, driver_type: StorageDriverType, factory: F)
+    where
+        F: Fn(&Figment, &StorageBackendRegistry) -> StorageBackendResult + Send + Sync + 'static,
+    {
+        self.factories.insert(driver_type, Box::new(factory));
+    }
+
+    /// Creates the backend of a storage driver type
+    ///
+    /// # Arguments
+    ///
+    /// * `driver_type` - The storage driver type
+    /// * `figment` - The config
+    ///
+    /// # Returns
+    ///
+    /// The backend or `None` if no backend is registered for the type
+    pub fn create(
+        &self,
+        driver_type: &StorageDriverType,
+        figment: &Figment,
+    ) -> Option<StorageBackendResult> {
+        self.factories
+            .get(driver_type)
+            .map(|factory| factory(figment, self))
+    }
+
+    /// Creates the storage driver configured in the `storage` section, including its cold
+    /// tier and the storage files are migrated from
+    ///
+    /// # Arguments
+    ///
+    /// * `figment` - The config
+    pub fn create_driver(
+        &self,
+        figment: &Figment,
+    ) -> Result<StorageDriver, Box<dyn Error + Send + Sync>> {
+        let config: StorageDriverFairingConfig = figment
+            .focus("storage")
+            .extract()
+            .map_err(|err| format!("Unable to load storage config: {}", err))?;
+        let mut backend = self.create_configured(&config.storage_type, figment)?;
+        if let Some(migrate_from) = &config.migrate_from {
+            let source = self.create_configured(migrate_from, figment)?;
+            backend = Arc::new(MigratingBackend::new(backend, source));
+        }
+        let mut driver = StorageDriver::new(backend);
+        if let Some(cold_storage_type) = &config.cold_storage_type {
+            driver = driver.with_cold_tier(self.create_configured(cold_storage_type, figment)?);
+        }
+        Ok(driver)
+    }
+
+    /// Creates the backend of a storage driver type which has to be registered
+    fn create_configured(
+        &self,
+        driver_type: &StorageDriverType,
+        figment: &Figment,
+    ) -> StorageBackendResult {
+        self.create(driver_type, figment)
+            .ok_or_else(|| format!("Unknown storage type {}", driver_type))?
+            .map_err(|err| format!("Unable to load {} storage config: {}", driver_type, err).into())
+    }
 }
 
 impl Deref for StorageDriverGuard {
@@ -52,9 +363,24 @@ impl Deref for StorageDriverGuard {
     }
 }
 
+impl Default for StorageDriverFairing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl StorageDriverFairing {
     pub fn new() -> Self {
-        Self {}
+        Self::with_registry(StorageBackendRegistry::default())
+    }
+
+    /// Creates the fairing using a custom set of backends
+    ///
+    /// # Arguments
+    ///
+    /// * `registry` - The backends which can be selected using `storage_type`
+    pub fn with_registry(registry: StorageBackendRegistry) -> Self {
+        Self { registry }
     }
 }
 
@@ -68,36 +394,33 @@ impl Fairing for StorageDriverFairing {
     }
 
     async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
-        let driver = match rocket
-            .figment()
-            .focus("storage")
-            .extract::<StorageDriverFairingConfig>()
-            .expect("Unable to load storage config, is it defined in Rocket.toml?")
-            .storage_type
-        {
-            StorageDriverType::ObjectStorage => {
-                let config: ObjectStorageConfig =
-                    rocket.figment().focus("storage.object").extract().expect(
-                        "Unable to load object storage config, is it defined in Rocket.toml?",
-                    );
-                StorageDriver::object(Bucket::new(
-                    config.name,
-                    config.url,
-                    BucketCredentials::new(
-                        Credentials::from_keys(config.access_key, config.access_key_secret, None),
-                        config.region,
-                    ),
-                ))
+        let driver = match self.registry.create_driver(rocket.figment()) {
+            Ok(driver) => driver,
+            Err(err) => {
+                error!("{}", err);
+                return Err(rocket);
             }
-            StorageDriverType::Drive => {
-                let config: DriveStorageConfig =
-                    rocket.figment().focus("storage.drive").extract().expect(
-                        "Unable to load drive storage config, is it defined in Rocket.toml?",
-                    );
-                StorageDriver::drive(Path::new(config.path.as_str()).to_path_buf())
+        };
+        // A missing section disables encryption, a malformed one must not
+        let encryption: EncryptionConfig =
+            match rocket.figment().focus("storage.encryption").extract() {
+                Ok(encryption) => encryption,
+                Err(err) => {
+                    error!("Unable to load encryption config: {}", err);
+                    return Err(rocket);
+                }
+            };
+        let keyring = match Keyring::from_config(&encryption) {
+            Ok(keyring) => keyring,
+            Err(err) => {
+                error!(
+                    "Unable to load encryption keys, are they 64 hex characters long? ({})",
+                    err
+                );
+                return Err(rocket);
             }
         };
-        Ok(rocket.manage(driver))
+        Ok(rocket.manage(driver).manage(keyring))
     }
 }
 
@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use log::{error, info};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    tokio::{self, time},
+    Orbit, Rocket,
+};
+use serde::Deserialize;
+
+use crate::{
+    database::query::{
+        blob::lock_blob,
+        file::{find_unused_hot_storage_ids, update_files_tier},
+        since_epoch_in_ms,
+    },
+    storage::driver::{StorageDriver, StorageTier},
+};
+
+use super::database::PostgresPool;
+
+/// Default interval in which unused files are moved to the cold tier, time is in seconds
+const DEFAULT_TIERING_INTERVAL: u64 = 3600;
+/// Amount of storage objects moved per batch
+const TIERING_BATCH_SIZE: i64 = 100;
+const DAY_IN_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Periodically moves files which weren't used for a while from the hot to the cold tier
+pub struct StorageTieringFairing;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TieringConfig {
+    // Interval in which unused files are moved, time is in seconds
+    interval: Option<u64>,
+    // Files uploaded more than this amount of days ago are moved
+    max_age: Option<u64>,
+    // Files which weren't viewed for this amount of days are moved
+    max_idle: Option<u64>,
+}
+
+impl Default for StorageTieringFairing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StorageTieringFairing {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for StorageTieringFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Storage Tiering Fairing",
+            kind: Kind::Liftoff,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        let Some(storage) = rocket
+            .state::<StorageDriver>()
+            .filter(|storage| storage.has_cold_tier())
+        else {
+            return;
+        };
+        let Some(pool) = rocket.state::<PostgresPool>() else {
+            error!("Unable to start storage tiering, database is unavailable");
+            return;
+        };
+        let config: TieringConfig = rocket
+            .figment()
+            .focus("storage.tiering")
+            .extract()
+            .unwrap_or_default();
+        if config.max_age.is_none() && config.max_idle.is_none() {
+            error!("Unable to start storage tiering, neither max_age nor max_idle is configured");
+            return;
+        }
+
+        let pool = pool.clone();
+        let storage = storage.clone();
+        let mut shutdown = rocket.shutdown();
+        tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(
+                config.interval.unwrap_or(DEFAULT_TIERING_INTERVAL),
+            ));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => move_unused_files(&pool, &storage, &config).await,
+                    _ = &mut shutdown => break,
+                }
+            }
+        });
+    }
+}
+
+/// Moves all unused storage objects to the cold tier, objects are moved one by one so a
+/// storage failure only keeps the affected object in the hot tier until the next run
+async fn move_unused_files(pool: &PostgresPool, storage: &StorageDriver, config: &TieringConfig) {
+    let now = since_epoch_in_ms();
+    let before = |days: Option<u64>| days.map(|days| now - days as i64 * DAY_IN_MS);
+    loop {
+        let unused = match pool.begin().await {
+            Ok(mut transaction) => {
+                find_unused_hot_storage_ids(
+                    &mut transaction,
+                    before(config.max_age),
+                    before(config.max_idle),
+                    TIERING_BATCH_SIZE,
+                )
+                .await
+            }
+            Err(err) => Err(err),
+        };
+        let unused = match unused {
+            Ok(unused) => unused,
+            Err(err) => {
+                error!("Failed to query unused files: {}", err);
+                return;
+            }
+        };
+
+        let mut moved = 0;
+        for storage_id in &unused {
+            match move_to_cold_tier(pool, storage, storage_id).await {
+                Ok(()) => moved += 1,
+                Err(err) => error!(
+                    "Failed to move storage object {} to the cold tier: {}",
+                    storage_id, err
+                ),
+            }
+        }
+        if moved > 0 {
+            info!("Moved {} storage objects to the cold tier", moved);
+        }
+        // Stop once all unused objects are moved or if none of the batch could be moved
+        if (unused.len() as i64) < TIERING_BATCH_SIZE || moved == 0 {
+            return;
+        }
+    }
+}
+
+/// Copies a single storage object to the cold tier, the files are only switched to the
+/// cold tier once the copy is complete. Objects are copied as stored, so encrypted
+/// objects stay encrypted with their data key
+async fn move_to_cold_tier(
+    pool: &PostgresPool,
+    storage: &StorageDriver,
+    storage_id: &String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (hot, cold) = (
+        storage.tier(StorageTier::Hot),
+        storage.tier(StorageTier::Cold),
+    );
+    let metadata = hot.backend().head_file(storage_id).await?;
+    let (stream, content_type) = hot.backend().get_file(storage_id, None).await?;
+    cold.backend()
+        .save_file(storage_id, &content_type, metadata.size, stream)
+        .await?;
+
+    // Files sharing the object can't be added or deleted while their tier is switched
+    let mut transaction = pool.begin().await?;
+    lock_blob(&mut transaction, storage_id).await?;
+    let updated =
+        update_files_tier(&mut transaction, storage_id, StorageTier::Cold.as_str()).await?;
+    transaction.commit().await?;
+    if updated == 0 {
+        // The files were deleted while the object was copied
+        cold.delete_file(storage_id).await?;
+        return Ok(());
+    }
+
+    // Readers which loaded the file before the switch fall back to the cold tier
+    if let Err(err) = hot.delete_file(storage_id).await {
+        error!(
+            "Failed to delete storage object {} from the hot tier: {}",
+            storage_id, err
+        );
+    }
+    Ok(())
+}
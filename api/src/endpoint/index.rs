@@ -1,24 +1,70 @@
 use super::{
-    fairing::{database::PostgresDb, storage::StorageDriverGuard},
+    fairing::{
+        database::{PostgresDb, PostgresPool},
+        storage::StorageDriverGuard,
+    },
+    mime::resolve_content_type,
+    range::RangeRequest,
     v1::{error::Error, UploaderResult},
     SuccessReporter,
 };
-use crate::{database::query::file::find_file_by_id, GlobalConfig};
+use crate::{
+    database::{
+        file::FileEntity,
+        query::{
+            blob::release_blob,
+            file::{delete_file_by_id, find_file_by_id, increment_file_views, record_file_view},
+            since_epoch_in_ms,
+        },
+    },
+    storage::{
+        driver::{ByteRange, DeleteOnDropStream, FileStream},
+        encryption::Keyring,
+    },
+    GlobalConfig,
+};
 use build_info::BuildInfo;
+use log::warn;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use rocket::{
-    get,
-    http::ContentType,
-    response::{self, Responder},
+    get, head,
+    http::{ContentType, Status},
+    response::{self, Redirect, Responder},
     serde::json::Json,
-    Request, Response, State,
+    tokio, Either, Request, Response, State,
 };
 use serde::Serialize;
-use std::{io::Cursor, str::FromStr};
+use std::{
+    io::Cursor,
+    str::FromStr,
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Characters which have to be encoded in extended header parameters (RFC 8187)
+const HEADER_VALUE_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
 
 pub struct FileShowResponse {
-    data: Vec<u8>,
+    // Not present when only the headers should be sent (HEAD requests)
+    data: Option<FileStream>,
     content_type: String,
     cache_time: usize,
+    size: u64,
+    range: Option<ByteRange>,
+    etag: String,
+    last_modified: String,
+    disposition: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -38,11 +84,23 @@ impl ServerInfoResponse {
 }
 
 impl FileShowResponse {
-    pub fn new(data: Vec<u8>, content_type: String, cache_time: usize) -> Self {
+    pub fn new(
+        data: Option<FileStream>,
+        content_type: String,
+        cache_time: usize,
+        file: &FileEntity,
+        range: Option<ByteRange>,
+        download: bool,
+    ) -> Self {
         Self {
             data,
             content_type,
             cache_time,
+            size: file.size as u64,
+            range,
+            etag: entity_tag(file),
+            last_modified: last_modified(file),
+            disposition: content_disposition(file, download),
         }
     }
 }
@@ -50,7 +108,8 @@ impl FileShowResponse {
 #[rocket::async_trait]
 impl<'r> Responder<'r, 'static> for FileShowResponse {
     fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
-        Response::build()
+        let mut response = Response::build();
+        response
             .header(ContentType::from_str(&self.content_type).unwrap_or(ContentType::default()))
             .raw_header(
                 "Cache-Control",
@@ -60,8 +119,33 @@ impl<'r> Responder<'r, 'static> for FileShowResponse {
                     "no-cache".into()
                 },
             )
-            .streamed_body(Cursor::new(self.data))
-            .ok()
+            // Browsers must not guess another type than the one detected on upload
+            .raw_header("X-Content-Type-Options", "nosniff")
+            .raw_header("Accept-Ranges", "bytes")
+            .raw_header("ETag", self.etag)
+            .raw_header("Last-Modified", self.last_modified)
+            .raw_header("Content-Disposition", self.disposition);
+
+        let length = match self.range {
+            Some(range) => {
+                response.status(Status::PartialContent).raw_header(
+                    "Content-Range",
+                    format!("bytes {}-{}/{}", range.start, range.end, self.size),
+                );
+                range.size()
+            }
+            None => self.size,
+        };
+        match self.data {
+            // The length is known upfront, this allows clients to show download progress
+            // even though the body is streamed
+            Some(data) => response
+                .raw_header("Content-Length", length.to_string())
+                .streamed_body(data),
+            // Rocket strips the body from HEAD responses but keeps its size as Content-Length
+            None => response.sized_body(Some(length as usize), Cursor::new(Vec::new())),
+        };
+        response.ok()
     }
 }
 
@@ -71,23 +155,237 @@ pub async fn index() -> Json<ServerInfoResponse> {
     Json(ServerInfoResponse::new(info.crate_info.version.to_string()))
 }
 
-#[get("/<id>")]
+#[get("/<id>?<download>")]
 pub async fn show_file(
     id: &str,
+    download: bool,
+    database: PostgresDb,
+    storage: StorageDriverGuard,
+    config: &State<GlobalConfig>,
+    keyring: &State<Keyring>,
+    range: RangeRequest,
+) -> UploaderResult<Either<FileShowResponse, Redirect>> {
+    let mut transaction = database.begin().await.map_err(|_| Error::DatabaseError)?;
+    let file = find_file_by_id(&mut transaction, &strip_extension(id).to_string())
+        .await
+        .map_err(|_| Error::FileNotFoundError)?;
+    if file.is_expired(since_epoch_in_ms()) {
+        return Err(Error::FileExpiredError);
+    }
+
+    // Files with a view limit are always served entirely, otherwise partial requests
+    // would use up views without the file ever being fully viewed
+    let range = match file.max_views {
+        Some(_) => None,
+        None => range.resolve(file.size as u64, &entity_tag(&file), &last_modified(&file))?,
+    };
+    // Continuations of partial downloads don't count as a separate view
+    let counted = range.is_none_or(|range| range.start == 0);
+    let (file, burn, burn_object) = match file.max_views {
+        // The row stays locked while the view is counted, so concurrent views can't exceed
+        // the maximum. Files without a limit aren't locked, as they would be served one at a time
+        Some(_) => {
+            let file = increment_file_views(&mut transaction, &file.id, since_epoch_in_ms())
+                .await
+                .map_err(|_| Error::FileNotFoundError)?;
+            // The object itself is only deleted if no other file shares its contents
+            let burn = file.is_used_up();
+            let burn_object = if burn {
+                delete_file_by_id(&mut transaction, &file.id)
+                    .await
+                    .map_err(|_| Error::DatabaseError)?;
+                release_blob(&mut transaction, &file)
+                    .await
+                    .map_err(|_| Error::DatabaseError)?
+            } else {
+                false
+            };
+            (file, burn, burn_object)
+        }
+        None => (file, false, false),
+    };
+    // Views of files with a view limit are only counted once their contents could be opened,
+    // otherwise a failing read would use up the last view and leave its object behind.
+    // Other files aren't locked, so their transaction is committed right away
+    let transaction = match file.max_views {
+        Some(_) => Some(transaction),
+        None => {
+            transaction
+                .commit()
+                .await
+                .map_err(|_| Error::DatabaseError)?;
+            None
+        }
+    };
+    if counted && file.max_views.is_none() {
+        record_view(database.0.clone(), file.id.clone());
+    }
+
+    // Files are moved between tiers in the background, so they are read from their current tier
+    let storage = storage.tier(file.storage_tier());
+    // Objects which aren't encrypted can be served by the storage directly. Files with a view
+    // limit are always proxied, as the url could be shared without the views being counted
+    if file.data_key.is_none() && file.max_views.is_none() {
+        let presigned = storage
+            .presign_download(
+                &file.storage_id,
+                served_content_type(&file, config).as_deref(),
+                &content_disposition(&file, download),
+            )
+            .await;
+        match presigned {
+            Ok(Some(url)) => return Ok(Either::Right(Redirect::found(url))),
+            Ok(None) => {}
+            Err(err) => warn!("Failed to presign download of file {}: {}", file.id, err),
+        }
+    }
+
+    let data_key = keyring
+        .unwrap_data_key(file.data_key.as_deref())
+        .map_err(Error::from)?;
+    let (data, stored_content_type) = storage
+        .get_file(&file.storage_id, range, data_key.as_ref())
+        .await
+        .map_err(Error::from)?;
+    if let Some(transaction) = transaction {
+        transaction
+            .commit()
+            .await
+            .map_err(|_| Error::DatabaseError)?;
+    }
+
+    let (data, cache_time): (FileStream, usize) = if burn_object {
+        (
+            Box::pin(DeleteOnDropStream::new(
+                data,
+                storage,
+                file.storage_id.clone(),
+            )),
+            0,
+        )
+    } else if burn || file.max_views.is_some() {
+        // Caches would keep serving the file without the views being counted
+        (data, 0)
+    } else {
+        (data, config.cache_length.unwrap_or(0))
+    };
+    // Objects are shared between uploads, so the type stored with the file takes precedence
+    let content_type = served_content_type(&file, config).unwrap_or(stored_content_type);
+    Ok(Either::Left(FileShowResponse::new(
+        Some(data),
+        content_type,
+        cache_time,
+        &file,
+        range,
+        download,
+    )))
+}
+
+#[head("/<id>?<download>")]
+pub async fn head_file(
+    id: &str,
+    download: bool,
     database: PostgresDb,
     storage: StorageDriverGuard,
     config: &State<GlobalConfig>,
 ) -> UploaderResult<FileShowResponse> {
     let mut transaction = database.begin().await.map_err(|_| Error::DatabaseError)?;
-    let file = find_file_by_id(&mut transaction, &id.to_string())
+    let file = find_file_by_id(&mut transaction, &strip_extension(id).to_string())
         .await
         .map_err(|_| Error::FileNotFoundError)?;
-    let (data, content_type) = storage.get_file(&file.storage_id).await.unwrap();
+    if file.is_expired(since_epoch_in_ms()) {
+        return Err(Error::FileExpiredError);
+    }
+    let content_type = match served_content_type(&file, config) {
+        Some(content_type) => content_type,
+        None => {
+            storage
+                .tier(file.storage_tier())
+                .head_file(&file.storage_id)
+                .await
+                .map_err(Error::from)?
+                .content_type
+        }
+    };
     Ok(FileShowResponse::new(
-        data,
+        None,
         content_type,
         config.cache_length.unwrap_or(0),
+        &file,
+        None,
+        download,
     ))
 }
 
+/// Counts a view of a file without a view limit in the background, so downloads don't wait
+/// for each other while the row is updated
+fn record_view(pool: PostgresPool, id: String) {
+    tokio::spawn(async move {
+        let recorded = async {
+            let mut transaction = pool.begin().await?;
+            record_file_view(&mut transaction, &id, since_epoch_in_ms()).await?;
+            transaction.commit().await
+        };
+        if let Err(err) = recorded.await {
+            warn!("Failed to record view of file {}: {}", id, err);
+        }
+    });
+}
+
+/// Creates the entity tag of a file, as files can't be modified the upload time and size
+/// are enough to identify its contents
+fn entity_tag(file: &FileEntity) -> String {
+    format!("\"{:x}-{:x}\"", file.uploaded_at, file.size)
+}
+
+/// Resolves the content type a file is served with according to the configured policy
+fn served_content_type(file: &FileEntity, config: &GlobalConfig) -> Option<String> {
+    resolve_content_type(
+        file.content_type.as_deref(),
+        file.detected_content_type.as_deref(),
+        config.content_type_policy.unwrap_or_default(),
+    )
+}
+
+/// Removes the extension which may be appended to file urls
+pub(crate) fn strip_extension(id: &str) -> &str {
+    id.split_once('.').map_or(id, |(id, _)| id)
+}
+
+/// Creates the Content-Disposition header of a file, browsers use the original file name
+/// when saving the file
+///
+/// # Arguments
+///
+/// * `file` - The file
+/// * `download` - Whether the file should be downloaded instead of displayed
+fn content_disposition(file: &FileEntity, download: bool) -> String {
+    let disposition = if download { "attachment" } else { "inline" };
+    match &file.file_name {
+        Some(name) => {
+            // Older clients only understand the plain ascii `filename` parameter
+            let fallback: String = name
+                .chars()
+                .map(|c| match c {
+                    '"' | '\\' => '_',
+                    c if c.is_ascii() && !c.is_ascii_control() => c,
+                    _ => '_',
+                })
+                .collect();
+            format!(
+                "{}; filename=\"{}\"; filename*=UTF-8''{}",
+                disposition,
+                fallback,
+                utf8_percent_encode(name, HEADER_VALUE_ENCODE_SET)
+            )
+        }
+        None => disposition.into(),
+    }
+}
+
+/// Formats the upload time of a file as HTTP date
+fn last_modified(file: &FileEntity) -> String {
+    httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_millis(file.uploaded_at as u64))
+}
+
 build_info::build_info!(fn build_info);
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use rocket::{data::ByteUnit, tokio::io::AsyncReadExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{endpoint::v1::error::Error, storage::driver::FileStream};
+
+/// Amount of bytes read from the start of a file to detect its type
+pub const SNIFF_LENGTH: u64 = 8192;
+/// Content type served when the declared type can't be trusted
+const FALLBACK_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Decides which content type is served if the declared and detected type don't match
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentTypePolicy {
+    // The type detected from the file contents is served
+    #[default]
+    Detected,
+    // The type declared by the uploader is served
+    Declared,
+}
+
+/// Restricts which content types may be uploaded and how large they may be, patterns are
+/// either exact content types or prefixes ending with `*` (e.g. `image/*`)
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContentTypeRules {
+    // Patterns of content types which may be uploaded, everything is allowed if empty
+    allowed: Vec<String>,
+    // Patterns of content types which are always rejected
+    blocked: Vec<String>,
+    // Maximum file size per pattern, the most specific matching pattern applies
+    max_size: HashMap<String, ByteUnit>,
+}
+
+impl ContentTypeRules {
+    /// Checks whether a file may be uploaded
+    ///
+    /// # Arguments
+    ///
+    /// * `content_types` - The declared and detected content types of the file
+    /// * `size` - The file size in bytes
+    ///
+    /// # Returns
+    ///
+    /// An error if any of the content types is not allowed or the file exceeds its size limit
+    pub fn check(&self, content_types: &[&str], size: u64) -> Result<(), Error> {
+        for content_type in content_types {
+            let content_type = essence(content_type).to_ascii_lowercase();
+            let matches = |pattern: &String| matches_pattern(pattern, &content_type);
+            if self.blocked.iter().any(matches)
+                || (!self.allowed.is_empty() && !self.allowed.iter().any(matches))
+            {
+                return Err(Error::UnsupportedContentTypeError);
+            }
+
+            let max_size = self
+                .max_size
+                .iter()
+                .filter(|(pattern, _)| matches(pattern))
+                .max_by_key(|(pattern, _)| pattern.trim_end_matches('*').len())
+                .map(|(_, max_size)| max_size.as_u64());
+            if max_size.is_some_and(|max_size| size > max_size) {
+                return Err(Error::FileTooLargeError);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Detects the content type of a file using the magic bytes at its start
+///
+/// # Arguments
+///
+/// * `stream` - The file stream
+/// * `declared` - The content type declared by the uploader
+///
+/// # Returns
+///
+/// The detected content type or `None` if the type is unknown
+pub async fn detect_content_type(stream: FileStream, declared: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    stream
+        .take(SNIFF_LENGTH)
+        .read_to_end(&mut bytes)
+        .await
+        .ok()?;
+    sniff_content_type(&bytes, declared)
+}
+
+/// Detects the content type of a file using the magic bytes at its start
+///
+/// # Arguments
+///
+/// * `bytes` - The first `SNIFF_LENGTH` bytes of the file
+/// * `declared` - The content type declared by the uploader
+///
+/// # Returns
+///
+/// The detected content type or `None` if the type is unknown
+pub fn sniff_content_type(bytes: &[u8], declared: &str) -> Option<String> {
+    match infer::get(bytes) {
+        Some(kind) => Some(kind.mime_type().to_string()),
+        // The declared type would have been detected, so the contents don't match it
+        None if infer::is_mime_supported(essence(declared)) => {
+            Some(FALLBACK_CONTENT_TYPE.to_string())
+        }
+        None => None,
+    }
+}
+
+/// Resolves the content type a file is served with
+///
+/// # Arguments
+///
+/// * `declared` - The content type declared by the uploader
+/// * `detected` - The content type detected from the file contents
+/// * `policy` - Which type wins if both are known
+///
+/// # Returns
+///
+/// The content type to serve or `None` if neither is known
+pub fn resolve_content_type(
+    declared: Option<&str>,
+    detected: Option<&str>,
+    policy: ContentTypePolicy,
+) -> Option<String> {
+    match policy {
+        ContentTypePolicy::Detected => detected.or(declared),
+        ContentTypePolicy::Declared => declared.or(detected),
+    }
+    .map(String::from)
+}
+
+/// Checks whether a content type matches a pattern
+///
+/// # Arguments
+///
+/// * `pattern` - An exact content type or a prefix ending with `*`
+/// * `content_type` - The lowercase content type without parameters
+fn matches_pattern(pattern: &str, content_type: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    match pattern.strip_suffix('*') {
+        Some(prefix) => content_type.starts_with(prefix),
+        None => content_type == pattern,
+    }
+}
+
+/// Strips the parameters (e.g. `; charset=utf-8`) from a content type
+fn essence(content_type: &str) -> &str {
+    content_type
+        .split_once(';')
+        .map_or(content_type, |(essence, _)| essence)
+        .trim()
+}
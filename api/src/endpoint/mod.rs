@@ -2,6 +2,8 @@ use serde::Serialize;
 
 pub mod fairing;
 pub mod index;
+pub mod mime;
+pub mod range;
 pub mod v1;
 
 /// Responsible for displaying the success status of JSON responses
@@ -0,0 +1,174 @@
+use std::convert::Infallible;
+
+use rocket::{
+    outcome::Outcome,
+    request::{self, FromRequest},
+    Request,
+};
+
+use crate::storage::driver::ByteRange;
+
+use super::v1::{error::Error, UploaderResult};
+
+/// Request guard exposing the `Range` and `If-Range` headers of a request
+pub struct RangeRequest {
+    range: Option<String>,
+    if_range: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RangeRequest {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        Outcome::Success(RangeRequest {
+            range: headers.get_one("Range").map(String::from),
+            if_range: headers.get_one("If-Range").map(String::from),
+        })
+    }
+}
+
+impl RangeRequest {
+    /// Resolves the requested range against a file
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - The size of the file
+    /// * `etag` - The current entity tag of the file
+    /// * `last_modified` - The current last modified date of the file
+    ///
+    /// # Returns
+    ///
+    /// The range to serve or `None` if the entire file should be served
+    pub fn resolve(
+        &self,
+        size: u64,
+        etag: &str,
+        last_modified: &str,
+    ) -> UploaderResult<Option<ByteRange>> {
+        let Some(range) = &self.range else {
+            return Ok(None);
+        };
+        // If the validator does not match anymore the client has an outdated copy,
+        // so we have to send the entire file instead
+        if let Some(if_range) = &self.if_range {
+            if if_range != etag && if_range != last_modified {
+                return Ok(None);
+            }
+        }
+        parse_range(range, size)
+    }
+}
+
+/// Parses a `Range` header value, only single ranges are supported as
+/// servers are allowed to ignore ranges they do not want to serve
+///
+/// # Arguments
+///
+/// * `header` - The header value (e.g. `bytes=0-99`)
+/// * `size` - The size of the file
+///
+/// # Returns
+///
+/// The parsed range, `None` if the header should be ignored
+fn parse_range(header: &str, size: u64) -> UploaderResult<Option<ByteRange>> {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Ok(None);
+    };
+
+    let range = match (start.trim(), end.trim()) {
+        // Suffix range (e.g. `bytes=-500`) containing the last bytes of the file
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return Err(Error::RangeNotSatisfiableError(size)),
+            Ok(suffix) => ByteRange::new(size.saturating_sub(suffix), size.wrapping_sub(1)),
+            Err(_) => return Ok(None),
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => ByteRange::new(start, size.wrapping_sub(1)),
+            Err(_) => return Ok(None),
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => {
+                ByteRange::new(start, end.min(size.wrapping_sub(1)))
+            }
+            _ => return Ok(None),
+        },
+    };
+
+    if size == 0 || range.start >= size {
+        return Err(Error::RangeNotSatisfiableError(size));
+    }
+    Ok(Some(range))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(header: &str, size: u64) -> Option<(u64, u64)> {
+        parse_range(header, size)
+            .expect("range should be satisfiable")
+            .map(|range| (range.start, range.end))
+    }
+
+    fn is_unsatisfiable(header: &str, size: u64) -> bool {
+        matches!(
+            parse_range(header, size),
+            Err(Error::RangeNotSatisfiableError(unsatisfied)) if unsatisfied == size
+        )
+    }
+
+    #[test]
+    fn parses_bounded_range() {
+        assert_eq!(range("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(range("bytes=10-10", 1000), Some((10, 10)));
+    }
+
+    #[test]
+    fn clamps_end_to_size() {
+        assert_eq!(range("bytes=900-2000", 1000), Some((900, 999)));
+    }
+
+    #[test]
+    fn parses_open_range() {
+        assert_eq!(range("bytes=500-", 1000), Some((500, 999)));
+    }
+
+    #[test]
+    fn parses_suffix_range() {
+        assert_eq!(range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(range("bytes=-5000", 1000), Some((0, 999)));
+        assert!(is_unsatisfiable("bytes=-0", 1000));
+    }
+
+    #[test]
+    fn ignores_start_after_end() {
+        assert_eq!(range("bytes=100-50", 1000), None);
+    }
+
+    #[test]
+    fn rejects_start_outside_of_file() {
+        assert!(is_unsatisfiable("bytes=1000-1100", 1000));
+        assert!(is_unsatisfiable("bytes=1000-", 1000));
+        assert!(is_unsatisfiable("bytes=0-10", 0));
+    }
+
+    #[test]
+    fn ignores_multiple_ranges() {
+        assert_eq!(range("bytes=0-10,20-30", 1000), None);
+    }
+
+    #[test]
+    fn ignores_other_units_and_malformed_values() {
+        assert_eq!(range("items=0-10", 1000), None);
+        assert_eq!(range("bytes=a-b", 1000), None);
+        assert_eq!(range("bytes=10", 1000), None);
+    }
+}
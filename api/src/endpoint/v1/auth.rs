@@ -0,0 +1,58 @@
+use rand::{distributions::Alphanumeric, Rng};
+use rocket::{
+    http::Status,
+    outcome::{try_outcome, Outcome},
+    request::{self, FromRequest},
+    Request,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    database::{api_key::ApiKeyEntity, query::api_key::find_api_key_by_hash},
+    endpoint::{fairing::database::PostgresDb, v1::error::Error},
+};
+
+/// Length of newly generated api keys
+const API_KEY_LENGTH: usize = 48;
+
+/// Request guard which authenticates the request using the api key passed in the
+/// `Authorization` header
+pub struct ApiKey(pub ApiKeyEntity);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiKey {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let Some(key) = request.headers().get_one("Authorization") else {
+            return Outcome::Error((Status::Forbidden, Error::Unauthorized));
+        };
+        let database = try_outcome!(request.guard::<PostgresDb>().await);
+        let Ok(mut transaction) = database.begin().await else {
+            return Outcome::Error((Status::InternalServerError, Error::DatabaseError));
+        };
+
+        match find_api_key_by_hash(&mut transaction, &hash_api_key(key)).await {
+            Ok(entity) => Outcome::Success(ApiKey(entity)),
+            Err(_) => Outcome::Error((Status::Forbidden, Error::Unauthorized)),
+        }
+    }
+}
+
+/// Generates a new randomized api key, only its hash should ever be persisted
+pub fn generate_api_key() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(API_KEY_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Hashes an api key for storing and looking it up in the database
+///
+/// # Arguments
+///
+/// * `key` - The plain api key
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
@@ -20,11 +20,44 @@ pub enum Error {
     #[uploader(status_code = 403)]
     Unauthorized,
     #[error("The file is too large")]
-    #[uploader(status_code = 403)]
+    #[uploader(status_code = 413)]
     FileTooLargeError,
+    #[error("The content type of the file is not allowed")]
+    #[uploader(status_code = 415)]
+    UnsupportedContentTypeError,
     #[error("The file does not exist")]
     #[uploader(status_code = 404)]
     FileNotFoundError,
+    #[error("The file has expired")]
+    #[uploader(status_code = 410)]
+    FileExpiredError,
+    #[error("The requested expiration is invalid or exceeds the maximum")]
+    #[uploader(status_code = 400)]
+    InvalidExpirationError,
+    #[error("The maximum amount of views must be at least 1")]
+    #[uploader(status_code = 400)]
+    InvalidMaxViewsError,
+    #[error("The size of the file is invalid")]
+    #[uploader(status_code = 400)]
+    InvalidSizeError,
+    #[error("The pagination cursor is invalid")]
+    #[uploader(status_code = 400)]
+    InvalidCursorError,
+    #[error("Too many files were requested at once")]
+    #[uploader(status_code = 400)]
+    TooManyFilesError,
+    #[error("The requested range is not satisfiable")]
+    #[uploader(status_code = 416)]
+    RangeNotSatisfiableError(u64),
+    #[error("The file has not been uploaded yet")]
+    #[uploader(status_code = 409)]
+    UploadIncompleteError,
+    #[error("The uploaded file does not have the reserved size")]
+    #[uploader(status_code = 400)]
+    UploadSizeMismatchError,
+    #[error("Direct uploads are not available")]
+    #[uploader(status_code = 501)]
+    DirectUploadUnavailableError,
     #[error("Storage driver is not available")]
     #[uploader(status_code = 500)]
     StorageUnavailableError,
@@ -57,12 +90,17 @@ impl RocketErrorResponse {
 
 impl<'r> Responder<'r, 'static> for Error {
     fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
-        Response::build()
+        let mut response = Response::build();
+        response
             .merge(Json(RocketErrorResponse::new(self.to_string())).respond_to(request)?)
             .status(
                 Status::from_code(self.error_attr().status_code)
                     .unwrap_or(Status::InternalServerError),
-            )
-            .ok()
+            );
+        // Clients need the size of the file to request a valid range (RFC 9110 15.5.17)
+        if let Error::RangeNotSatisfiableError(size) = self {
+            response.raw_header("Content-Range", format!("bytes */{}", size));
+        }
+        response.ok()
     }
 }
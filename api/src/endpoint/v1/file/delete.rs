@@ -1,10 +1,11 @@
 use crate::{
-    database::query::file::delete_file_by_secret,
+    database::query::{blob::release_blob, file::delete_file_by_secret},
     endpoint::{
         fairing::{database::PostgresDb, storage::StorageDriverGuard},
         v1::{error::Error, UploaderResult},
     },
 };
+use log::error;
 use rocket::{delete, get};
 
 // Also offer deletion using GET requests as some screenshotting / uploading tools do that unfortunately
@@ -38,14 +39,27 @@ async fn inner_delete(
     let file = delete_file_by_secret(&mut transaction, &id.to_string())
         .await
         .map_err(|_| Error::FileNotFoundError)?;
-    storage
-        .delete_file(&file.storage_id.as_str())
+    // The object may still be used by other files with the same contents
+    let unused = release_blob(&mut transaction, &file)
         .await
-        .map_err(|err| Error::from(err))?;
-
+        .map_err(|_| Error::DatabaseError)?;
     transaction
         .commit()
         .await
         .map_err(|_| Error::DatabaseError)?;
+
+    // The row is gone already, an object which fails to delete is only orphaned
+    if unused {
+        if let Err(err) = storage
+            .tier(file.storage_tier())
+            .delete_file(file.storage_id.as_str())
+            .await
+        {
+            error!(
+                "Failed to delete storage object of file {}: {}",
+                file.id, err
+            );
+        }
+    }
     Ok(())
 }
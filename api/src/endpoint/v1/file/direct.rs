@@ -0,0 +1,306 @@
+use std::{collections::HashMap, time::Duration};
+
+use log::warn;
+use rocket::{post, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    database::{
+        file::FileEntity,
+        query::{
+            file::{
+                activate_file, delete_file_by_id, find_file_by_id, find_pending_file, save_file,
+            },
+            since_epoch_in_ms, PgTransaction,
+        },
+    },
+    endpoint::{
+        fairing::{database::PostgresDb, storage::StorageDriverGuard},
+        mime::{detect_content_type, SNIFF_LENGTH},
+        v1::{auth::ApiKey, error::Error, UploaderResult},
+        SuccessReporter,
+    },
+    storage::{
+        driver::{ByteRange, StorageDriver, StorageTier},
+        encryption::Keyring,
+    },
+    GlobalConfig,
+};
+
+use super::upload::{
+    expiration_timestamp, generate_file_id, resolve_expiration, sanitize_file_name,
+    upload_response, UploadResponse,
+};
+
+/// Default time clients have to complete direct uploads, time is in seconds
+const DEFAULT_PENDING_UPLOAD_EXPIRATION: u64 = 3600;
+/// Content type reserved if the client doesn't declare one
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+#[derive(Debug, Deserialize)]
+pub struct UploadInitRequest {
+    file_name: Option<String>,
+    content_type: Option<String>,
+    // Exact size of the file in bytes
+    size: u64,
+    // Time in seconds after which the file is deleted, starts once the upload is completed
+    expires_in: Option<u64>,
+    // Amount of views after which the file is deleted
+    max_views: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadInitResponse {
+    #[serde(flatten)]
+    success: SuccessReporter,
+    id: String,
+    // The file has to be sent to this url using a PUT request with the reserved content type
+    upload_url: String,
+    content_type: String,
+    // Headers which have to be sent along with the PUT request
+    upload_headers: HashMap<String, String>,
+    // The upload has to be completed before this time in ms since the unix epoch
+    upload_expires_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UploadCompleteRequest {
+    id: String,
+}
+
+/// Reserves a file which the client uploads directly to the storage using the returned url,
+/// the file is only available once the upload is completed using `/file/upload/complete`
+#[post("/file/upload/init", data = "<request>")]
+pub async fn init(
+    request: Json<UploadInitRequest>,
+    storage: StorageDriverGuard,
+    database: PostgresDb,
+    config: &State<GlobalConfig>,
+    keyring: &State<Keyring>,
+    api_key: UploaderResult<ApiKey>,
+) -> UploaderResult<Json<UploadInitResponse>> {
+    let api_key = api_key?;
+    // The api never sees the contents, so they couldn't be encrypted
+    if keyring.current_key_id().is_some() {
+        return Err(Error::DirectUploadUnavailableError);
+    }
+    if request.max_views.is_some_and(|max_views| max_views < 1) {
+        return Err(Error::InvalidMaxViewsError);
+    }
+    let size = i64::try_from(request.size).map_err(|_| Error::InvalidSizeError)?;
+    let content_type = request
+        .content_type
+        .clone()
+        .unwrap_or(DEFAULT_CONTENT_TYPE.to_string());
+    config
+        .content_types
+        .check(&[content_type.as_str()], request.size)?;
+
+    let reserved_at = since_epoch_in_ms();
+    let expires_at = resolve_expiration(request.expires_in, config)?
+        .map(|expires_in| expiration_timestamp(reserved_at, expires_in))
+        .transpose()?;
+    let pending_expiration = config
+        .pending_upload_expiration
+        .unwrap_or(DEFAULT_PENDING_UPLOAD_EXPIRATION);
+    let pending_until = expiration_timestamp(reserved_at, pending_expiration)?;
+    let id = generate_file_id(config.file_id_length);
+
+    // Directly uploaded files own their object, as their hash is never computed
+    let file = FileEntity {
+        id: id.clone(),
+        storage_id: Uuid::new_v4().to_string().replace("-", ""),
+        secret: Uuid::new_v4().to_string().replace("-", ""),
+        uploaded_at: reserved_at,
+        size,
+        api_key_id: Some(api_key.0.id),
+        expires_at,
+        views: 0,
+        max_views: request.max_views,
+        file_name: request.file_name.as_deref().and_then(sanitize_file_name),
+        content_type: Some(content_type.clone()),
+        hash: None,
+        detected_content_type: None,
+        data_key: None,
+        tier: StorageTier::Hot.to_string(),
+        last_viewed_at: None,
+        missing_at: None,
+        pending_until: Some(pending_until),
+    };
+    let mut transaction = database.begin().await.map_err(|_| Error::DatabaseError)?;
+    save_file(&mut transaction, &file)
+        .await
+        .map_err(|_| Error::DatabaseError)?;
+    // The client uploads to a separate object which is copied once the upload is completed
+    let upload = storage
+        .tier(StorageTier::Hot)
+        .presign_upload(
+            &file.upload_storage_id(),
+            &content_type,
+            Duration::from_secs(pending_expiration),
+        )
+        .await
+        .map_err(Error::from)?
+        .ok_or(Error::DirectUploadUnavailableError)?;
+
+    transaction
+        .commit()
+        .await
+        .map_err(|_| Error::DatabaseError)?;
+    Ok(Json(UploadInitResponse {
+        success: SuccessReporter::new(true),
+        id,
+        upload_url: upload.url,
+        content_type,
+        upload_headers: upload.headers,
+        upload_expires_at: pending_until,
+    }))
+}
+
+/// Makes a directly uploaded file available after verifying the uploaded object
+#[post("/file/upload/complete", data = "<request>")]
+pub async fn complete(
+    request: Json<UploadCompleteRequest>,
+    storage: StorageDriverGuard,
+    database: PostgresDb,
+    config: &State<GlobalConfig>,
+    api_key: UploaderResult<ApiKey>,
+) -> UploaderResult<UploadResponse> {
+    let api_key = api_key?;
+    let mut transaction = database.begin().await.map_err(|_| Error::DatabaseError)?;
+    let file = find_pending_file(&mut transaction, &request.id, &api_key.0.id)
+        .await
+        .map_err(|_| Error::FileNotFoundError)?;
+    // The upload is verified outside of transactions, so the row isn't locked while the
+    // storage copies it
+    transaction
+        .commit()
+        .await
+        .map_err(|_| Error::DatabaseError)?;
+    let now = since_epoch_in_ms();
+    // Expired reservations are deleted by the reaper
+    if file
+        .pending_until
+        .is_some_and(|pending_until| pending_until <= now)
+    {
+        return Err(Error::FileExpiredError);
+    }
+
+    let storage = storage.tier(file.storage_tier());
+    let upload_id = file.upload_storage_id();
+    storage
+        .head_file(&upload_id)
+        .await
+        .map_err(|_| Error::UploadIncompleteError)?;
+    // The upload url stays valid after completion, so the upload is copied to an object the
+    // client can't write to and only the copy is verified and served
+    storage
+        .copy_file(&upload_id, &file.storage_id)
+        .await
+        .map_err(Error::from)?;
+    if let Err(err) = storage.delete_file(&upload_id).await {
+        warn!(
+            "Failed to delete upload object of file {}: {}",
+            file.id, err
+        );
+    }
+    let metadata = storage
+        .head_file(&file.storage_id)
+        .await
+        .map_err(Error::from)?;
+
+    let (detected_content_type, rejection) = if metadata.size != file.size as u64 {
+        (None, Some(Error::UploadSizeMismatchError))
+    } else {
+        // The declared type can't be trusted, so the real type is detected from the contents
+        let declared = file.content_type.clone().unwrap_or_default();
+        let detected_content_type = match metadata.size {
+            0 => None,
+            _ => {
+                let (stream, _) = storage
+                    .get_file(
+                        &file.storage_id,
+                        Some(ByteRange::new(0, SNIFF_LENGTH - 1)),
+                        None,
+                    )
+                    .await
+                    .map_err(Error::from)?;
+                detect_content_type(stream, &declared).await
+            }
+        };
+        let content_types: Vec<&str> = std::iter::once(declared.as_str())
+            .chain(detected_content_type.as_deref())
+            .collect();
+        let rejection = config
+            .content_types
+            .check(&content_types, metadata.size)
+            .err();
+        (detected_content_type, rejection)
+    };
+
+    // The reservation may have been completed, deleted or expired while the upload was verified
+    let mut transaction = database.begin().await.map_err(|_| Error::DatabaseError)?;
+    let Ok(file) = find_pending_file(&mut transaction, &file.id, &api_key.0.id).await else {
+        // The copy is only still in use if the file was completed concurrently
+        if let Err(sqlx::Error::RowNotFound) = find_file_by_id(&mut transaction, &file.id).await {
+            if let Err(err) = storage.delete_file(&file.storage_id).await {
+                warn!(
+                    "Failed to delete object of removed file {}: {}",
+                    file.id, err
+                );
+            }
+        }
+        return Err(Error::FileNotFoundError);
+    };
+    if file
+        .pending_until
+        .is_some_and(|pending_until| pending_until <= since_epoch_in_ms())
+    {
+        discard_upload(transaction, &storage, &file).await?;
+        return Err(Error::FileExpiredError);
+    }
+    if let Some(err) = rejection {
+        discard_upload(transaction, &storage, &file).await?;
+        return Err(err);
+    }
+
+    let file = activate_file(
+        &mut transaction,
+        &file.id,
+        detected_content_type.as_deref(),
+        now,
+    )
+    .await
+    .map_err(|_| Error::DatabaseError)?;
+    transaction
+        .commit()
+        .await
+        .map_err(|_| Error::DatabaseError)?;
+    Ok(upload_response(
+        config,
+        &file.id,
+        &file.secret,
+        file.file_name.as_deref(),
+        file.expires_at,
+    ))
+}
+
+/// Deletes a reserved file and its uploaded object after the upload was rejected
+async fn discard_upload(
+    mut transaction: PgTransaction<'_>,
+    storage: &StorageDriver,
+    file: &FileEntity,
+) -> UploaderResult<()> {
+    delete_file_by_id(&mut transaction, &file.id)
+        .await
+        .map_err(|_| Error::DatabaseError)?;
+    transaction
+        .commit()
+        .await
+        .map_err(|_| Error::DatabaseError)?;
+    storage
+        .delete_file(&file.storage_id)
+        .await
+        .map_err(Error::from)
+}
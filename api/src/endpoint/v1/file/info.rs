@@ -0,0 +1,89 @@
+use rocket::{get, serde::json::Json};
+use serde::Serialize;
+
+use crate::{
+    database::{
+        file::FileEntity,
+        query::{file::find_file_by_id, since_epoch_in_ms},
+    },
+    endpoint::{
+        fairing::{database::PostgresDb, storage::StorageDriverGuard},
+        index::strip_extension,
+        v1::{error::Error, UploaderResult},
+        SuccessReporter,
+    },
+};
+
+/// Publicly visible information about a file, must never contain the secret or storage id
+#[derive(Debug, Serialize)]
+pub struct FileInfo {
+    id: String,
+    size: i64,
+    uploaded_at: i64,
+    content_type: Option<String>,
+    detected_content_type: Option<String>,
+    file_name: Option<String>,
+    expires_at: Option<i64>,
+    views: i64,
+    max_views: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileInfoResponse {
+    #[serde(flatten)]
+    success: SuccessReporter,
+    #[serde(flatten)]
+    file: FileInfo,
+}
+
+impl From<FileEntity> for FileInfo {
+    fn from(file: FileEntity) -> Self {
+        Self {
+            id: file.id,
+            size: file.size,
+            uploaded_at: file.uploaded_at,
+            content_type: file.content_type,
+            detected_content_type: file.detected_content_type,
+            file_name: file.file_name,
+            expires_at: file.expires_at,
+            views: file.views,
+            max_views: file.max_views,
+        }
+    }
+}
+
+impl FileInfoResponse {
+    pub fn new(file: FileInfo) -> Self {
+        Self {
+            success: SuccessReporter::new(true),
+            file,
+        }
+    }
+}
+
+// Ranked lower as `/file/delete/info` would collide with the deletion route
+#[get("/file/<id>/info", rank = 2)]
+pub async fn info(
+    id: &str,
+    database: PostgresDb,
+    storage: StorageDriverGuard,
+) -> UploaderResult<Json<FileInfoResponse>> {
+    let mut transaction = database.begin().await.map_err(|_| Error::DatabaseError)?;
+    let mut file = find_file_by_id(&mut transaction, &strip_extension(id).to_string())
+        .await
+        .map_err(|_| Error::FileNotFoundError)?;
+    if file.is_expired(since_epoch_in_ms()) {
+        return Err(Error::FileExpiredError);
+    }
+
+    // Files uploaded before the content type was stored in the database only have it in storage
+    if file.content_type.is_none() {
+        let metadata = storage
+            .tier(file.storage_tier())
+            .head_file(&file.storage_id)
+            .await
+            .map_err(Error::from)?;
+        file.content_type = Some(metadata.content_type);
+    }
+    Ok(Json(FileInfoResponse::new(FileInfo::from(file))))
+}
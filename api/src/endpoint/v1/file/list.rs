@@ -0,0 +1,174 @@
+use log::error;
+use rocket::{delete, get, serde::json::Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::query::{
+        blob::release_blob,
+        file::{delete_files_by_ids, list_files_by_api_key, FileFilter},
+    },
+    endpoint::{
+        fairing::{database::PostgresDb, storage::StorageDriverGuard},
+        v1::{auth::ApiKey, error::Error, UploaderResult},
+        SuccessReporter,
+    },
+};
+
+use super::info::FileInfo;
+
+/// Amount of files returned per page if not specified
+const DEFAULT_PAGE_SIZE: i64 = 50;
+/// Maximum amount of files which can be listed or deleted at once
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Serialize)]
+pub struct FileListResponse {
+    #[serde(flatten)]
+    success: SuccessReporter,
+    files: Vec<FileInfo>,
+    // Passed as `cursor` to fetch the next page, not present on the last page
+    next_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BulkDeleteRequest {
+    ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkDeleteResponse {
+    #[serde(flatten)]
+    success: SuccessReporter,
+    deleted: Vec<String>,
+    // Files which don't exist or were uploaded using another api key
+    not_found: Vec<String>,
+}
+
+/// Lists the files uploaded using the api key of the request, newest first
+///
+/// `content_type` may end with a wildcard (e.g. `image/*`) and is matched against the
+/// detected content type, or the declared one if none was detected. `from` and `to` are
+/// timestamps in ms since the unix epoch
+#[get("/files?<cursor>&<limit>&<content_type>&<from>&<to>")]
+pub async fn list(
+    cursor: Option<&str>,
+    limit: Option<i64>,
+    content_type: Option<&str>,
+    from: Option<i64>,
+    to: Option<i64>,
+    database: PostgresDb,
+    api_key: UploaderResult<ApiKey>,
+) -> UploaderResult<Json<FileListResponse>> {
+    let api_key = api_key?;
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let filter = FileFilter {
+        cursor: cursor.map(decode_cursor).transpose()?,
+        content_type: content_type.map(content_type_pattern),
+        uploaded_after: from,
+        uploaded_before: to,
+    };
+
+    let mut transaction = database.begin().await.map_err(|_| Error::DatabaseError)?;
+    let files = list_files_by_api_key(&mut transaction, &api_key.0.id, &filter, limit)
+        .await
+        .map_err(|_| Error::DatabaseError)?;
+
+    let next_cursor = match files.last() {
+        Some(last) if files.len() as i64 == limit => {
+            Some(encode_cursor(last.uploaded_at, &last.id))
+        }
+        _ => None,
+    };
+    Ok(Json(FileListResponse {
+        success: SuccessReporter::new(true),
+        files: files.into_iter().map(FileInfo::from).collect(),
+        next_cursor,
+    }))
+}
+
+/// Deletes multiple files uploaded using the api key of the request
+#[delete("/files", data = "<request>")]
+pub async fn bulk_delete(
+    request: Json<BulkDeleteRequest>,
+    database: PostgresDb,
+    storage: StorageDriverGuard,
+    api_key: UploaderResult<ApiKey>,
+) -> UploaderResult<Json<BulkDeleteResponse>> {
+    let api_key = api_key?;
+    if request.ids.len() as i64 > MAX_PAGE_SIZE {
+        return Err(Error::TooManyFilesError);
+    }
+
+    let mut transaction = database.begin().await.map_err(|_| Error::DatabaseError)?;
+    let files = delete_files_by_ids(&mut transaction, &request.ids, &api_key.0.id)
+        .await
+        .map_err(|_| Error::DatabaseError)?;
+    // Objects may still be used by other files with the same contents
+    let mut unused = Vec::new();
+    for file in &files {
+        if release_blob(&mut transaction, file)
+            .await
+            .map_err(|_| Error::DatabaseError)?
+        {
+            unused.push(file);
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .map_err(|_| Error::DatabaseError)?;
+
+    // The rows are gone already, objects which fail to delete are only orphaned and
+    // don't affect the files which are still served
+    for file in unused {
+        if let Err(err) = storage
+            .tier(file.storage_tier())
+            .delete_file(&file.storage_id)
+            .await
+        {
+            error!(
+                "Failed to delete storage object of file {}: {}",
+                file.id, err
+            );
+        }
+    }
+
+    let deleted: Vec<String> = files.into_iter().map(|file| file.id).collect();
+    let not_found = request
+        .ids
+        .iter()
+        .filter(|id| !deleted.contains(id))
+        .cloned()
+        .collect();
+    Ok(Json(BulkDeleteResponse {
+        success: SuccessReporter::new(true),
+        deleted,
+        not_found,
+    }))
+}
+
+/// Encodes the position of a file into a pagination cursor
+fn encode_cursor(uploaded_at: i64, id: &str) -> String {
+    format!("{}-{}", uploaded_at, id)
+}
+
+/// Decodes a pagination cursor created by [`encode_cursor`]
+fn decode_cursor(cursor: &str) -> UploaderResult<(i64, String)> {
+    let (uploaded_at, id) = cursor.split_once('-').ok_or(Error::InvalidCursorError)?;
+    let uploaded_at = uploaded_at
+        .parse::<i64>()
+        .map_err(|_| Error::InvalidCursorError)?;
+    Ok((uploaded_at, id.to_string()))
+}
+
+/// Converts a content type filter into a SQL `LIKE` pattern, a trailing `*` matches anything
+fn content_type_pattern(content_type: &str) -> String {
+    let escaped = content_type
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    match escaped.strip_suffix('*') {
+        Some(prefix) => format!("{}%", prefix),
+        None => escaped,
+    }
+}
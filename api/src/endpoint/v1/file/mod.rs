@@ -1,2 +1,5 @@
 pub mod delete;
+pub mod direct;
+pub mod info;
+pub mod list;
 pub mod upload;
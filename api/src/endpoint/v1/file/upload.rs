@@ -1,31 +1,44 @@
-use std::convert::Infallible;
+use std::path::Path;
 
 use rand::distributions::Alphanumeric;
 use rand::Rng;
 use rocket::form::{Form, FromForm};
 use rocket::fs::TempFile;
 use rocket::http::ContentType;
-use rocket::outcome::Outcome;
-use rocket::request::{self, FromRequest};
 use rocket::serde::json::Json;
-use rocket::{post, Request, Responder, State};
+use rocket::{post, Responder, State};
 use serde::Serialize;
 use uuid::Uuid;
 
-use crate::database::query::file::save_file;
+use crate::database::file::FileEntity;
+use crate::database::query::{
+    blob::acquire_blob,
+    file::{clear_files_missing, find_file_by_hash, save_file},
+    since_epoch_in_ms,
+};
 use crate::endpoint::fairing::database::PostgresDb;
 use crate::endpoint::fairing::storage::StorageDriverGuard;
+use crate::endpoint::mime::{resolve_content_type, sniff_content_type, SNIFF_LENGTH};
+use crate::endpoint::v1::auth::ApiKey;
 use crate::endpoint::v1::error::Error;
-use crate::endpoint::v1::{convert_to_bytes, UploaderResult};
+use crate::endpoint::v1::{hash_and_sniff_file_stream, open_file_stream, UploaderResult};
+use crate::storage::{driver::StorageTier, encryption::Keyring};
 use crate::GlobalConfig;
 
+/// Maximum amount of characters kept from the original file name
+const MAX_FILE_NAME_LENGTH: usize = 255;
+/// Maximum length of extensions appended to urls
+const MAX_EXTENSION_LENGTH: usize = 10;
+
 #[derive(FromForm)]
 pub struct FileData<'r> {
     file: TempFile<'r>,
+    // Time in seconds after which the file is deleted
+    expires_in: Option<u64>,
+    // Amount of views after which the file is deleted
+    max_views: Option<i64>,
 }
 
-pub struct AuthToken(String);
-
 #[derive(Responder)]
 #[response(status = 200, content_type = "json")]
 pub struct UploadResponse {
@@ -36,25 +49,17 @@ pub struct UploadResponse {
 pub struct UploadResponseData {
     url: String,
     deletion_url: String,
+    expires_at: Option<i64>,
 }
 
 impl UploadResponse {
-    pub fn new(url: String, deletion_url: String) -> Self {
+    pub fn new(url: String, deletion_url: String, expires_at: Option<i64>) -> Self {
         Self {
-            inner: Json(UploadResponseData { url, deletion_url }),
-        }
-    }
-}
-
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for AuthToken {
-    type Error = Infallible;
-
-    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
-        let token = request.headers().get_one("Authorization");
-        match token {
-            Some(token) => Outcome::Success(AuthToken(token.to_string())),
-            None => Outcome::Success(AuthToken("".into())),
+            inner: Json(UploadResponseData {
+                url,
+                deletion_url,
+                expires_at,
+            }),
         }
     }
 }
@@ -65,68 +70,282 @@ pub async fn upload(
     storage: StorageDriverGuard,
     database: PostgresDb,
     config: &State<GlobalConfig>,
-    token: AuthToken,
+    keyring: &State<Keyring>,
+    api_key: UploaderResult<ApiKey>,
 ) -> UploaderResult<UploadResponse> {
+    let api_key = api_key?;
     let mut transaction = database.begin().await.map_err(|_| Error::DatabaseError)?;
     let bucket_id = Uuid::new_v4().to_string().replace("-", "");
     let secret = Uuid::new_v4().to_string().replace("-", "");
     let id = generate_file_id(config.file_id_length);
-
-    if let Some(auth_key) = &config.auth_key {
-        if auth_key != &token.0 {
-            return Err(Error::Unauthorized);
-        }
+    let file_name = file_data
+        .file
+        .raw_name()
+        .and_then(|name| sanitize_file_name(name.dangerous_unsafe_unsanitized_raw().as_str()));
+    let content_type = file_data
+        .file
+        .content_type()
+        .unwrap_or(&ContentType::default())
+        .to_string();
+    let size = file_data.file.len() as i64;
+    let uploaded_at = since_epoch_in_ms();
+    let expires_at = resolve_expiration(file_data.expires_in, config)?
+        .map(|expires_in| expiration_timestamp(uploaded_at, expires_in))
+        .transpose()?;
+    if file_data.max_views.is_some_and(|max_views| max_views < 1) {
+        return Err(Error::InvalidMaxViewsError);
     }
 
+    // Files with the same contents share a single object in the storage driver. The declared
+    // type can't be trusted, so the real type is detected from the contents read for the hash
+    let (hash, sniffed) = hash_and_sniff_file_stream(
+        open_file_stream(&file_data.file).await?,
+        SNIFF_LENGTH as usize,
+    )
+    .await?;
+    let detected_content_type = sniff_content_type(&sniffed, &content_type);
+    let content_types: Vec<&str> = std::iter::once(content_type.as_str())
+        .chain(detected_content_type.as_deref())
+        .collect();
+    config.content_types.check(&content_types, size as u64)?;
+
+    let served_content_type = resolve_content_type(
+        Some(&content_type),
+        detected_content_type.as_deref(),
+        config.content_type_policy.unwrap_or_default(),
+    )
+    .unwrap_or(content_type.clone());
+    let blob = acquire_blob(&mut transaction, &hash, &bucket_id, &size)
+        .await
+        .map_err(|_| Error::DatabaseError)?;
+    // New objects get their own data key and start in the hot tier, files sharing an
+    // object have to use its key and tier
+    let (data_key, wrapped_data_key, tier, save_object) = if blob.storage_id == bucket_id {
+        let (data_key, wrapped_data_key) =
+            keyring.generate_data_key().map_err(Error::from)?.unzip();
+        (data_key, wrapped_data_key, StorageTier::Hot, true)
+    } else {
+        // The key and tier of the object can't be guessed if its file was deleted meanwhile
+        let shared = find_file_by_hash(&mut transaction, &hash)
+            .await
+            .map_err(|_| Error::DatabaseError)?
+            .ok_or(Error::DatabaseError)?;
+        let tier = shared.storage_tier();
+        // Objects which went missing are stored again, otherwise the upload would be linked
+        // to nothing
+        let missing = shared.missing_at.is_some()
+            || !storage
+                .tier(tier)
+                .file_exists(&blob.storage_id)
+                .await
+                .map_err(Error::from)?;
+        let data_key = if missing {
+            keyring
+                .unwrap_data_key(shared.data_key.as_deref())
+                .map_err(Error::from)?
+        } else {
+            None
+        };
+        (data_key, shared.data_key, tier, missing)
+    };
+
     // As we use transactions, if the file upload fails the file will be dropped
     save_file(
         &mut transaction,
-        &id,
-        &bucket_id.to_string(),
-        &secret,
-        &(file_data.file.len() as i64),
+        &FileEntity {
+            id: id.clone(),
+            storage_id: blob.storage_id.clone(),
+            secret: secret.clone(),
+            uploaded_at,
+            size,
+            api_key_id: Some(api_key.0.id),
+            expires_at,
+            views: 0,
+            max_views: file_data.max_views,
+            file_name: file_name.clone(),
+            content_type: Some(content_type.clone()),
+            hash: Some(hash),
+            detected_content_type,
+            data_key: wrapped_data_key,
+            tier: tier.to_string(),
+            last_viewed_at: None,
+            missing_at: None,
+            pending_until: None,
+        },
     )
     .await
     .map_err(|_| Error::DatabaseError)?;
-    storage
-        .save_file(
-            &bucket_id,
-            &file_data
-                .file
-                .content_type()
-                .unwrap_or(&ContentType::default())
-                .to_string(),
-            convert_to_bytes(
-                &mut file_data
-                    .file
-                    .open()
-                    .await
-                    .map_err(|_| Error::FileConvertError)?,
+    if save_object {
+        storage
+            .tier(tier)
+            .save_file(
+                &blob.storage_id,
+                &served_content_type,
+                file_data.file.len(),
+                open_file_stream(&file_data.file).await?,
+                data_key.as_ref(),
             )
-            .await?,
-        )
-        .await
-        .map_err(|err| Error::from(err))?;
+            .await
+            .map_err(Error::from)?;
+        if blob.storage_id != bucket_id {
+            clear_files_missing(&mut transaction, std::slice::from_ref(&blob.storage_id))
+                .await
+                .map_err(|_| Error::DatabaseError)?;
+        }
+    }
 
     transaction
         .commit()
         .await
         .map_err(|_| Error::DatabaseError)?;
-    // TODO: Make api url configurable
-    Ok(UploadResponse::new(
-        format!("{}/{}", config.public_url, &id),
-        format!("{}/api/v1/file/delete/{}", config.public_url, &secret),
+    Ok(upload_response(
+        config,
+        &id,
+        &secret,
+        file_name.as_deref(),
+        expires_at,
     ))
 }
 
+/// Creates the response sent after a file was uploaded
+///
+/// # Arguments
+///
+/// * `config` - The global config
+/// * `id` - The public id of the file
+/// * `secret` - The secret used to delete the file
+/// * `file_name` - The sanitized file name, its extension is appended to the url if enabled
+/// * `expires_at` - The time the file expires at in ms since the unix epoch
+pub(super) fn upload_response(
+    config: &GlobalConfig,
+    id: &str,
+    secret: &str,
+    file_name: Option<&str>,
+    expires_at: Option<i64>,
+) -> UploadResponse {
+    // TODO: Make api url configurable
+    let extension = match config.append_extension {
+        Some(true) => file_name
+            .and_then(url_extension)
+            .map(|extension| format!(".{}", extension))
+            .unwrap_or_default(),
+        _ => String::new(),
+    };
+    UploadResponse::new(
+        format!("{}/{}{}", config.public_url, id, extension),
+        format!("{}/api/v1/file/delete/{}", config.public_url, secret),
+        expires_at,
+    )
+}
+
+/// Resolves the expiration of an upload against the configured default and maximum
+///
+/// # Arguments
+///
+/// * `expires_in` - The expiration requested by the uploader in seconds
+/// * `config` - The global config
+///
+/// # Returns
+///
+/// The expiration in seconds or `None` if the file should never expire
+pub(super) fn resolve_expiration(
+    expires_in: Option<u64>,
+    config: &GlobalConfig,
+) -> UploaderResult<Option<u64>> {
+    let expires_in = expires_in.or(config.default_expiration);
+    match (expires_in, config.max_expiration) {
+        (Some(0), _) => Err(Error::InvalidExpirationError),
+        (Some(expires_in), Some(max)) if expires_in > max => Err(Error::InvalidExpirationError),
+        // Files may not live forever if a maximum is configured
+        (None, Some(max)) => Ok(Some(max)),
+        (expires_in, _) => Ok(expires_in),
+    }
+}
+
+/// Adds an expiration to a timestamp
+///
+/// # Arguments
+///
+/// * `from` - The timestamp in ms since the unix epoch
+/// * `expires_in` - The expiration in seconds
+///
+/// # Returns
+///
+/// The timestamp the expiration ends at or an error if it can't be represented
+pub(super) fn expiration_timestamp(from: i64, expires_in: u64) -> UploaderResult<i64> {
+    i64::try_from(expires_in)
+        .ok()
+        .and_then(|expires_in| expires_in.checked_mul(1000))
+        .and_then(|expires_in| from.checked_add(expires_in))
+        .ok_or(Error::InvalidExpirationError)
+}
+
+/// Sanitizes the file name sent by the uploader so it can safely be stored and sent back
+/// in headers, path components and control characters are removed
+///
+/// # Arguments
+///
+/// * `raw` - The unsanitized file name
+///
+/// # Returns
+///
+/// The sanitized file name or `None` if nothing usable is left
+pub(super) fn sanitize_file_name(raw: &str) -> Option<String> {
+    let name = raw.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*'))
+        .take(MAX_FILE_NAME_LENGTH)
+        .collect();
+    let name = name.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Extracts the extension of a file name if it is safe to be used in urls
+fn url_extension(file_name: &str) -> Option<&str> {
+    Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .filter(|extension| {
+            extension.len() <= MAX_EXTENSION_LENGTH
+                && extension.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
 /// Generates a randomized file id
 ///
 /// # Arguments
 /// * `size` - The amount of characters to generate
-fn generate_file_id(size: usize) -> String {
+pub(super) fn generate_file_id(size: usize) -> String {
     rand::thread_rng()
         .sample_iter(&Alphanumeric)
         .take(size)
         .map(char::from)
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_expiration_timestamp() {
+        assert_eq!(expiration_timestamp(1_000, 60).unwrap(), 61_000);
+    }
+
+    #[test]
+    fn rejects_overflowing_expiration() {
+        assert!(matches!(
+            expiration_timestamp(1_000, u64::MAX),
+            Err(Error::InvalidExpirationError)
+        ));
+        assert!(matches!(
+            expiration_timestamp(1_000, i64::MAX as u64 / 1000 + 1),
+            Err(Error::InvalidExpirationError)
+        ));
+        assert!(matches!(
+            expiration_timestamp(i64::MAX - 500, 1),
+            Err(Error::InvalidExpirationError)
+        ));
+    }
+}
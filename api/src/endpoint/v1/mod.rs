@@ -1,8 +1,15 @@
+use std::io::Cursor;
+
 use rocket::{
-    tokio::io::{AsyncBufRead, AsyncReadExt},
+    fs::TempFile,
+    tokio::{fs::File, io::AsyncReadExt},
     Route,
 };
+use sha2::{Digest, Sha256};
+
+use crate::storage::driver::FileStream;
 
+pub mod auth;
 pub mod error;
 pub mod file;
 
@@ -12,28 +19,88 @@ pub type UploaderResult<T> = std::result::Result<T, error::Error>;
 pub fn create_v1_routes() -> Vec<Route> {
     rocket::routes![
         file::upload::upload,
+        file::direct::init,
+        file::direct::complete,
         file::delete::delete,
-        file::delete::delete_get
+        file::delete::delete_get,
+        file::info::info,
+        file::list::list,
+        file::list::bulk_delete
     ]
 }
 
-/// Converts a tokio buffer (from form data) to vector of bytes
+/// Opens an uploaded file as an owned byte stream without reading it into memory
 ///
 /// # Arguments
 ///
-/// * `stream` - The mutable byte buf
+/// * `file` - The uploaded temporary file
 ///
 /// # Returns
 ///
-/// The converted vector of bytes
-pub(crate) async fn convert_to_bytes<T>(stream: &mut T) -> Result<Vec<u8>, error::Error>
-where
-    T: AsyncBufRead + AsyncReadExt + Unpin,
-{
+/// The file stream
+pub(crate) async fn open_file_stream(file: &TempFile<'_>) -> Result<FileStream, error::Error> {
+    if let Some(path) = file.path() {
+        let file = File::open(path)
+            .await
+            .map_err(|_| error::Error::FileConvertError)?;
+        return Ok(Box::pin(file));
+    }
+
+    // Files without a path are buffered in memory by rocket already, so copying them is fine
     let mut bytes: Vec<u8> = Vec::new();
-    stream
+    file.open()
+        .await
+        .map_err(|_| error::Error::FileConvertError)?
         .read_to_end(&mut bytes)
         .await
         .map_err(|_| error::Error::FileConvertError)?;
-    return Ok(bytes);
+    Ok(Box::pin(Cursor::new(bytes)))
+}
+
+/// Computes the SHA-256 hash of a file stream without reading it into memory at once
+///
+/// # Arguments
+///
+/// * `stream` - The file stream
+///
+/// # Returns
+///
+/// The hex encoded hash
+pub async fn hash_file_stream(stream: FileStream) -> Result<String, error::Error> {
+    hash_and_sniff_file_stream(stream, 0)
+        .await
+        .map(|(hash, _)| hash)
+}
+
+/// Computes the SHA-256 hash of a file stream and keeps the bytes at its start, so the
+/// content type can be detected without reading the file again
+///
+/// # Arguments
+///
+/// * `stream` - The file stream
+/// * `sniff_length` - The amount of bytes to keep
+///
+/// # Returns
+///
+/// The hex encoded hash and the bytes at the start of the stream
+pub(crate) async fn hash_and_sniff_file_stream(
+    mut stream: FileStream,
+    sniff_length: usize,
+) -> Result<(String, Vec<u8>), error::Error> {
+    let mut hasher = Sha256::new();
+    let mut sniffed = Vec::with_capacity(sniff_length);
+    let mut buffer = vec![0_u8; 64 * 1024];
+    loop {
+        let read = stream
+            .read(&mut buffer)
+            .await
+            .map_err(|_| error::Error::FileConvertError)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        let missing = sniff_length - sniffed.len();
+        sniffed.extend_from_slice(&buffer[..read.min(missing)]);
+    }
+    Ok((hex::encode(hasher.finalize()), sniffed))
 }
@@ -0,0 +1,43 @@
+use endpoint::mime::{ContentTypePolicy, ContentTypeRules};
+use serde::{Deserialize, Serialize};
+
+pub mod database;
+pub mod endpoint;
+pub mod s3;
+pub mod storage;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GlobalConfig {
+    // Public server url
+    public_url: String,
+    // Length of the file id used for "shwoing" the file
+    file_id_length: usize,
+    // Defines a Cache-Control header, time is in seconds
+    cache_length: Option<usize>,
+    // Expiration applied to uploads which don't request one, time is in seconds
+    default_expiration: Option<u64>,
+    // Maximum expiration uploads may request, time is in seconds
+    max_expiration: Option<u64>,
+    // Interval in which expired files are deleted, time is in seconds
+    reaper_interval: Option<u64>,
+    // Interval in which missing copies of replicated files are restored, time is in seconds
+    repair_interval: Option<u64>,
+    // Interval in which objects without files and files without objects are searched, time is
+    // in seconds
+    reconcile_interval: Option<u64>,
+    // Whether objects without files are deleted by the reconciler, they are only reported if
+    // not enabled
+    delete_orphans: Option<bool>,
+    // Time objects without files are kept before they are deleted, time is in seconds
+    orphan_grace_period: Option<u64>,
+    // Time clients have to complete direct uploads before their reservation is deleted, time
+    // is in seconds
+    pending_upload_expiration: Option<u64>,
+    // Appends the extension of the original file name to generated urls
+    append_extension: Option<bool>,
+    // Whether the declared or detected content type is served if they don't match
+    content_type_policy: Option<ContentTypePolicy>,
+    // Allowed and blocked content types and their maximum sizes
+    #[serde(default)]
+    content_types: ContentTypeRules,
+}
@@ -1,36 +1,36 @@
-use crate::endpoint::v1::create_v1_routes;
-use endpoint::fairing::{database::PostgresFairing, storage::StorageDriverFairing};
+use api::{
+    endpoint::{
+        self,
+        fairing::{
+            database::PostgresFairing, reaper::FileReaperFairing,
+            reconciler::StorageReconcilerFairing, repair::StorageRepairFairing,
+            storage::StorageDriverFairing, tiering::StorageTieringFairing,
+        },
+        v1::create_v1_routes,
+    },
+    GlobalConfig,
+};
 use rocket::{fairing::AdHoc, routes};
-use serde::{Deserialize, Serialize};
-
-pub mod database;
-pub mod endpoint;
-pub mod s3;
-pub mod storage;
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GlobalConfig {
-    // Public server url
-    public_url: String,
-    // Length of the file id used for "shwoing" the file
-    file_id_length: usize,
-    // Defines a Cache-Control header, time is in seconds
-    cache_length: Option<usize>,
-    // If not empty requires an authentication header containing this key for uploads
-    auth_key: Option<String>,
-}
 
 #[rocket::main]
-async fn main() -> Result<(), rocket::Error> {
+async fn main() -> Result<(), Box<rocket::Error>> {
     rocket::build()
         .mount("/api/v1/", create_v1_routes())
         .mount(
             "/",
-            routes![endpoint::index::index, endpoint::index::show_file],
+            routes![
+                endpoint::index::index,
+                endpoint::index::show_file,
+                endpoint::index::head_file
+            ],
         )
         .attach(AdHoc::config::<GlobalConfig>())
         .attach(StorageDriverFairing::new())
         .attach(PostgresFairing::new())
+        .attach(FileReaperFairing::new())
+        .attach(StorageRepairFairing::new())
+        .attach(StorageTieringFairing::new())
+        .attach(StorageReconcilerFairing::new())
         .launch()
         .await?;
     Ok(())
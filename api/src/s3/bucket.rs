@@ -3,21 +3,60 @@ use async_trait::async_trait;
 use aws_sdk_s3::{
     error::SdkError,
     operation::{
+        abort_multipart_upload::{AbortMultipartUploadError, AbortMultipartUploadOutput},
+        complete_multipart_upload::{CompleteMultipartUploadError, CompleteMultipartUploadOutput},
+        copy_object::{CopyObjectError, CopyObjectOutput},
+        create_multipart_upload::{CreateMultipartUploadError, CreateMultipartUploadOutput},
         delete_object::{DeleteObjectError, DeleteObjectOutput},
         get_object::{GetObjectError, GetObjectOutput},
+        head_object::{HeadObjectError, HeadObjectOutput},
+        list_objects_v2::{ListObjectsV2Error, ListObjectsV2Output},
         put_object::{PutObjectError, PutObjectOutput},
+        upload_part::{UploadPartError, UploadPartOutput},
     },
+    presigning::{PresignedRequest, PresigningConfig},
     primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart, ServerSideEncryption, StorageClass},
     Client, Config,
 };
 
-use super::credentials::BucketCredentials;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use super::{
+    credentials::BucketCredentials,
+    encryption::{BucketEncryption, CustomerKey},
+};
+
+/// Characters of the source key which are encoded in the copy source, slashes separate the
+/// key prefix and stay as they are
+const COPY_SOURCE_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
 
 /// Provides access to object-based storage buckets that are accessible using amazons s3 api spec
 #[derive(Debug, Clone)]
 pub struct Bucket {
     client: Client,
     name: String,
+    // Prepended to the keys of all objects, lets multiple instances share a bucket
+    key_prefix: String,
+    // Storage class new objects are stored in, the provider's default is used if not present
+    storage_class: Option<StorageClass>,
+    // Encryption applied to new objects, the bucket's default is used if not present
+    encryption: Option<BucketEncryption>,
+}
+
+/// Decides how the bucket name is passed to the endpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BucketAddressing {
+    // The bucket name is part of the path (e.g. `https://endpoint/bucket/key`)
+    #[default]
+    Path,
+    // The bucket name is part of the host (e.g. `https://bucket.endpoint/key`)
+    VirtualHost,
 }
 
 #[async_trait]
@@ -27,11 +66,65 @@ pub trait BucketOperations {
     /// # Arguments
     ///
     /// * `key` - The key of the object
+    /// * `range` - The byte range to fetch (e.g. `bytes=0-99`), fetches the whole object if not present
     ///
     /// # Returns
     ///
     /// The object
-    async fn get(&self, key: &str) -> Result<GetObjectOutput, SdkError<GetObjectError>>;
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<&str>,
+    ) -> Result<GetObjectOutput, SdkError<GetObjectError>>;
+
+    /// Gets the metadata of an object without its contents
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the object
+    ///
+    /// # Returns
+    ///
+    /// The object metadata
+    async fn head(&self, key: &str) -> Result<HeadObjectOutput, SdkError<HeadObjectError>>;
+
+    /// Creates a url which allows anyone to get an object from the bucket until it expires
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the object
+    /// * `presigning` - The expiration of the url
+    /// * `content_type` - Overrides the Content-Type header of the response
+    /// * `content_disposition` - Overrides the Content-Disposition header of the response
+    ///
+    /// # Returns
+    ///
+    /// The presigned request containing the url
+    async fn presign_get(
+        &self,
+        key: &str,
+        presigning: PresigningConfig,
+        content_type: Option<&str>,
+        content_disposition: Option<&str>,
+    ) -> Result<PresignedRequest, SdkError<GetObjectError>>;
+
+    /// Creates a url which allows anyone to put an object into the bucket until it expires
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the object
+    /// * `presigning` - The expiration of the url
+    /// * `content_type` - The content type the object has to be uploaded with
+    ///
+    /// # Returns
+    ///
+    /// The presigned request containing the url
+    async fn presign_put(
+        &self,
+        key: &str,
+        presigning: PresigningConfig,
+        content_type: &str,
+    ) -> Result<PresignedRequest, SdkError<PutObjectError>>;
 
     /// Puts an object into the bucket
     ///
@@ -39,6 +132,8 @@ pub trait BucketOperations {
     ///
     /// * `key` - The key of the object
     /// * `bytes` - The bytes of the object to be created
+    /// * `content_length` - The amount of bytes in the stream
+    /// * `content_type` - The content type of the object
     ///
     /// # Returns
     ///
@@ -47,9 +142,41 @@ pub trait BucketOperations {
         &self,
         key: &str,
         bytes: ByteStream,
+        content_length: u64,
         content_type: Option<&str>,
     ) -> Result<PutObjectOutput, SdkError<PutObjectError>>;
 
+    /// Copies an object within the bucket without downloading it, objects of up to 5 GiB
+    /// can be copied this way
+    ///
+    /// # Arguments
+    ///
+    /// * `source_key` - The key of the object to copy
+    /// * `target_key` - The key of the created object
+    ///
+    /// # Returns
+    ///
+    /// Result of the copy
+    async fn copy(
+        &self,
+        source_key: &str,
+        target_key: &str,
+    ) -> Result<CopyObjectOutput, SdkError<CopyObjectError>>;
+
+    /// Lists the objects of the bucket, a page contains up to 1000 objects
+    ///
+    /// # Arguments
+    ///
+    /// * `continuation_token` - The token of the page to fetch, fetches the first page if not present
+    ///
+    /// # Returns
+    ///
+    /// The page of objects
+    async fn list(
+        &self,
+        continuation_token: Option<&str>,
+    ) -> Result<ListObjectsV2Output, SdkError<ListObjectsV2Error>>;
+
     /// Deletes an object from the bucket
     ///
     /// # Arguments
@@ -60,6 +187,79 @@ pub trait BucketOperations {
     ///
     /// Result of the delete
     async fn delete(&self, key: &str) -> Result<DeleteObjectOutput, SdkError<DeleteObjectError>>;
+
+    /// Starts a multipart upload, the object is created once the upload is completed
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the object
+    /// * `content_type` - The content type of the object
+    ///
+    /// # Returns
+    ///
+    /// The started upload containing its id
+    async fn create_multipart(
+        &self,
+        key: &str,
+        content_type: Option<&str>,
+    ) -> Result<CreateMultipartUploadOutput, SdkError<CreateMultipartUploadError>>;
+
+    /// Uploads a part of a multipart upload, every part except the last one has to be at
+    /// least 5 MiB
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the object
+    /// * `upload_id` - The id of the multipart upload
+    /// * `part_number` - The position of the part, starting at 1
+    /// * `bytes` - The bytes of the part
+    /// * `content_length` - The amount of bytes in the part
+    ///
+    /// # Returns
+    ///
+    /// The uploaded part containing its entity tag
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        bytes: ByteStream,
+        content_length: u64,
+    ) -> Result<UploadPartOutput, SdkError<UploadPartError>>;
+
+    /// Completes a multipart upload by joining its parts into the object
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the object
+    /// * `upload_id` - The id of the multipart upload
+    /// * `parts` - The uploaded parts ordered by their part number
+    ///
+    /// # Returns
+    ///
+    /// Result of the completion
+    async fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<CompletedPart>,
+    ) -> Result<CompleteMultipartUploadOutput, SdkError<CompleteMultipartUploadError>>;
+
+    /// Aborts a multipart upload and deletes the parts uploaded so far
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the object
+    /// * `upload_id` - The id of the multipart upload
+    ///
+    /// # Returns
+    ///
+    /// Result of the abort
+    async fn abort_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<AbortMultipartUploadOutput, SdkError<AbortMultipartUploadError>>;
 }
 
 impl Bucket {
@@ -68,54 +268,241 @@ impl Bucket {
     /// # Arguments
     ///
     /// * `name` - The name of the bucket
-    /// * `endpoint` - The endpoint of the bucket (url to access)
+    /// * `endpoint` - The endpoint of the bucket (url to access), resolved from the region for
+    ///   AWS if not present
     /// * `credentials` - The credentials required to access the bucket
+    /// * `addressing` - How the bucket name is passed to the endpoint
     ///
     /// # Returns
     /// The configured bucket
-    pub fn new(name: String, endpoint: String, credentials: BucketCredentials) -> Self {
+    pub fn new(
+        name: String,
+        endpoint: Option<String>,
+        credentials: BucketCredentials,
+        addressing: BucketAddressing,
+    ) -> Self {
+        let mut config = Config::builder()
+            .region(credentials.region)
+            .credentials_provider(credentials.credentials)
+            .force_path_style(addressing == BucketAddressing::Path)
+            .behavior_version_latest();
+        config.set_endpoint_url(endpoint);
         Self {
-            client: Client::from_conf(
-                Config::builder()
-                    .endpoint_url(endpoint)
-                    .region(credentials.region)
-                    .credentials_provider(credentials.credentials)
-                    .force_path_style(true)
-                    .behavior_version_latest()
-                    .build(),
-            ),
+            client: Client::from_conf(config.build()),
             name,
+            key_prefix: String::new(),
+            storage_class: None,
+            encryption: None,
         }
     }
 
+    /// Stores all objects below a prefix, a `/` is appended if missing
+    ///
+    /// # Arguments
+    ///
+    /// * `key_prefix` - The prefix prepended to all keys
+    pub fn with_key_prefix(mut self, key_prefix: &str) -> Self {
+        let key_prefix = key_prefix.trim_matches('/');
+        self.key_prefix = match key_prefix.is_empty() {
+            true => String::new(),
+            false => format!("{}/", key_prefix),
+        };
+        self
+    }
+
+    /// Stores new objects in a storage class (e.g. `STANDARD_IA`)
+    ///
+    /// # Arguments
+    ///
+    /// * `storage_class` - The name of the storage class
+    pub fn with_storage_class(mut self, storage_class: &str) -> Self {
+        self.storage_class = Some(StorageClass::from(storage_class));
+        self
+    }
+
+    /// Encrypts new objects using server-side encryption
+    ///
+    /// # Arguments
+    ///
+    /// * `encryption` - The encryption applied by the bucket
+    pub fn with_encryption(mut self, encryption: BucketEncryption) -> Self {
+        self.encryption = Some(encryption);
+        self
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    /// Returns whether objects can be accessed using presigned urls, which isn't possible
+    /// with customer provided keys as clients would need to know them
+    pub fn supports_presigning(&self) -> bool {
+        self.customer_key().is_none()
+    }
+
+    /// Removes the key prefix from the key of a listed object
+    ///
+    /// # Returns
+    ///
+    /// The key without prefix or `None` if the object doesn't belong to this bucket access,
+    /// keys below a further prefix belong to another bucket access sharing the bucket
+    pub fn strip_key_prefix<'a>(&self, key: &'a str) -> Option<&'a str> {
+        key.strip_prefix(self.key_prefix.as_str())
+            .filter(|key| !key.contains('/'))
+    }
+
+    fn object_key(&self, key: &str) -> String {
+        format!("{}{}", self.key_prefix, key)
+    }
+
+    fn customer_key(&self) -> Option<&CustomerKey> {
+        self.encryption
+            .as_ref()
+            .and_then(BucketEncryption::customer_key)
+    }
+
+    fn encryption_algorithm(&self) -> Option<ServerSideEncryption> {
+        self.encryption
+            .as_ref()
+            .and_then(BucketEncryption::algorithm)
+    }
+
+    fn kms_key_id(&self) -> Option<String> {
+        self.encryption
+            .as_ref()
+            .and_then(BucketEncryption::kms_key_id)
+    }
 }
 
 #[async_trait]
 impl BucketOperations for Bucket {
-    async fn get(&self, key: &str) -> Result<GetObjectOutput, SdkError<GetObjectError>> {
+    async fn get(
+        &self,
+        key: &str,
+        range: Option<&str>,
+    ) -> Result<GetObjectOutput, SdkError<GetObjectError>> {
         self.client
             .get_object()
             .bucket(self.name())
-            .key(key)
+            .key(self.object_key(key))
+            .set_range(range.map(String::from))
+            .set_sse_customer_algorithm(self.customer_key().map(CustomerKey::algorithm))
+            .set_sse_customer_key(self.customer_key().map(CustomerKey::key))
+            .set_sse_customer_key_md5(self.customer_key().map(CustomerKey::key_md5))
             .send()
             .await
     }
 
+    async fn head(&self, key: &str) -> Result<HeadObjectOutput, SdkError<HeadObjectError>> {
+        self.client
+            .head_object()
+            .bucket(self.name())
+            .key(self.object_key(key))
+            .set_sse_customer_algorithm(self.customer_key().map(CustomerKey::algorithm))
+            .set_sse_customer_key(self.customer_key().map(CustomerKey::key))
+            .set_sse_customer_key_md5(self.customer_key().map(CustomerKey::key_md5))
+            .send()
+            .await
+    }
+
+    async fn presign_get(
+        &self,
+        key: &str,
+        presigning: PresigningConfig,
+        content_type: Option<&str>,
+        content_disposition: Option<&str>,
+    ) -> Result<PresignedRequest, SdkError<GetObjectError>> {
+        self.client
+            .get_object()
+            .bucket(self.name())
+            .key(self.object_key(key))
+            .set_response_content_type(content_type.map(String::from))
+            .set_response_content_disposition(content_disposition.map(String::from))
+            .presigned(presigning)
+            .await
+    }
+
+    async fn presign_put(
+        &self,
+        key: &str,
+        presigning: PresigningConfig,
+        content_type: &str,
+    ) -> Result<PresignedRequest, SdkError<PutObjectError>> {
+        self.client
+            .put_object()
+            .bucket(self.name())
+            .key(self.object_key(key))
+            .content_type(content_type)
+            .set_server_side_encryption(self.encryption_algorithm())
+            .set_ssekms_key_id(self.kms_key_id())
+            .set_storage_class(self.storage_class.clone())
+            .presigned(presigning)
+            .await
+    }
+
     async fn put(
         &self,
         key: &str,
         bytes: ByteStream,
+        content_length: u64,
         content_type: Option<&str>,
     ) -> Result<PutObjectOutput, SdkError<PutObjectError>> {
         self.client
             .put_object()
             .bucket(self.name())
-            .key(key)
+            .key(self.object_key(key))
             .body(bytes)
+            .content_length(content_length as i64)
             .content_type(content_type.unwrap_or("application/octet-stream"))
+            .set_server_side_encryption(self.encryption_algorithm())
+            .set_ssekms_key_id(self.kms_key_id())
+            .set_storage_class(self.storage_class.clone())
+            .set_sse_customer_algorithm(self.customer_key().map(CustomerKey::algorithm))
+            .set_sse_customer_key(self.customer_key().map(CustomerKey::key))
+            .set_sse_customer_key_md5(self.customer_key().map(CustomerKey::key_md5))
+            .send()
+            .await
+    }
+
+    async fn copy(
+        &self,
+        source_key: &str,
+        target_key: &str,
+    ) -> Result<CopyObjectOutput, SdkError<CopyObjectError>> {
+        let copy_source = format!(
+            "{}/{}",
+            self.name(),
+            utf8_percent_encode(&self.object_key(source_key), COPY_SOURCE_ENCODE_SET)
+        );
+        self.client
+            .copy_object()
+            .bucket(self.name())
+            .key(self.object_key(target_key))
+            .copy_source(copy_source)
+            .set_server_side_encryption(self.encryption_algorithm())
+            .set_ssekms_key_id(self.kms_key_id())
+            .set_storage_class(self.storage_class.clone())
+            .set_copy_source_sse_customer_algorithm(self.customer_key().map(CustomerKey::algorithm))
+            .set_copy_source_sse_customer_key(self.customer_key().map(CustomerKey::key))
+            .set_copy_source_sse_customer_key_md5(self.customer_key().map(CustomerKey::key_md5))
+            .set_sse_customer_algorithm(self.customer_key().map(CustomerKey::algorithm))
+            .set_sse_customer_key(self.customer_key().map(CustomerKey::key))
+            .set_sse_customer_key_md5(self.customer_key().map(CustomerKey::key_md5))
+            .send()
+            .await
+    }
+
+    async fn list(
+        &self,
+        continuation_token: Option<&str>,
+    ) -> Result<ListObjectsV2Output, SdkError<ListObjectsV2Error>> {
+        self.client
+            .list_objects_v2()
+            .bucket(self.name())
+            .set_prefix((!self.key_prefix.is_empty()).then(|| self.key_prefix.clone()))
+            // Objects of bucket accesses using a longer prefix are only returned as common prefix
+            .delimiter("/")
+            .set_continuation_token(continuation_token.map(String::from))
             .send()
             .await
     }
@@ -124,8 +511,121 @@ impl BucketOperations for Bucket {
         self.client
             .delete_object()
             .bucket(self.name())
-            .key(key)
+            .key(self.object_key(key))
+            .send()
+            .await
+    }
+
+    async fn create_multipart(
+        &self,
+        key: &str,
+        content_type: Option<&str>,
+    ) -> Result<CreateMultipartUploadOutput, SdkError<CreateMultipartUploadError>> {
+        self.client
+            .create_multipart_upload()
+            .bucket(self.name())
+            .key(self.object_key(key))
+            .content_type(content_type.unwrap_or("application/octet-stream"))
+            .set_server_side_encryption(self.encryption_algorithm())
+            .set_ssekms_key_id(self.kms_key_id())
+            .set_storage_class(self.storage_class.clone())
+            .set_sse_customer_algorithm(self.customer_key().map(CustomerKey::algorithm))
+            .set_sse_customer_key(self.customer_key().map(CustomerKey::key))
+            .set_sse_customer_key_md5(self.customer_key().map(CustomerKey::key_md5))
+            .send()
+            .await
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        bytes: ByteStream,
+        content_length: u64,
+    ) -> Result<UploadPartOutput, SdkError<UploadPartError>> {
+        self.client
+            .upload_part()
+            .bucket(self.name())
+            .key(self.object_key(key))
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .set_sse_customer_algorithm(self.customer_key().map(CustomerKey::algorithm))
+            .set_sse_customer_key(self.customer_key().map(CustomerKey::key))
+            .set_sse_customer_key_md5(self.customer_key().map(CustomerKey::key_md5))
+            .body(bytes)
+            .content_length(content_length as i64)
+            .send()
+            .await
+    }
+
+    async fn complete_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<CompletedPart>,
+    ) -> Result<CompleteMultipartUploadOutput, SdkError<CompleteMultipartUploadError>> {
+        self.client
+            .complete_multipart_upload()
+            .bucket(self.name())
+            .key(self.object_key(key))
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+    }
+
+    async fn abort_multipart(
+        &self,
+        key: &str,
+        upload_id: &str,
+    ) -> Result<AbortMultipartUploadOutput, SdkError<AbortMultipartUploadError>> {
+        self.client
+            .abort_multipart_upload()
+            .bucket(self.name())
+            .key(self.object_key(key))
+            .upload_id(upload_id)
             .send()
             .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use aws_credential_types::Credentials;
+
+    use super::*;
+
+    fn bucket() -> Bucket {
+        let credentials = BucketCredentials::new(
+            Credentials::new("key", "secret", None, None, "test"),
+            Some("us-east-1".to_string()),
+        );
+        Bucket::new(
+            "bucket".to_string(),
+            None,
+            credentials,
+            BucketAddressing::Path,
+        )
+    }
+
+    #[test]
+    fn strips_key_prefix() {
+        let bucket = bucket().with_key_prefix("/uploads/");
+        assert_eq!(bucket.strip_key_prefix("uploads/abc"), Some("abc"));
+        assert_eq!(bucket.strip_key_prefix("abc"), None);
+        assert_eq!(bucket.strip_key_prefix("other/abc"), None);
+        assert_eq!(bucket.strip_key_prefix("uploads/nested/abc"), None);
+    }
+
+    #[test]
+    fn ignores_prefixed_keys_without_key_prefix() {
+        let bucket = bucket();
+        assert_eq!(bucket.strip_key_prefix("abc"), Some("abc"));
+        assert_eq!(bucket.strip_key_prefix("uploads/abc"), None);
+    }
+}
@@ -1,11 +1,20 @@
-use aws_config::Region;
-use aws_credential_types::Credentials;
+use std::env;
 
-const DEFAULT_REGION: &str = "weur"; // Default Cloudflare R2 location for 'Western Europe'
+use aws_config::{
+    ecs::EcsCredentialsProvider, environment::EnvironmentVariableCredentialsProvider,
+    imds::credentials::ImdsCredentialsProvider, meta::credentials::CredentialsProviderChain,
+    profile::ProfileFileCredentialsProvider, provider_config::ProviderConfig,
+    web_identity_token::WebIdentityTokenCredentialsProvider, Region,
+};
+use aws_credential_types::{provider::SharedCredentialsProvider, Credentials};
+
+/// Region used if neither the config nor the environment set one, also accepted by most s3
+/// compatible providers (e.g. Cloudflare R2 treats it as `auto`)
+const DEFAULT_REGION: &str = "us-east-1";
 
 /// Stores information required to authenticate with a S3 bucket
 pub struct BucketCredentials {
-    pub credentials: Credentials,
+    pub credentials: SharedCredentialsProvider,
     pub region: Region,
 }
 
@@ -15,14 +24,69 @@ impl BucketCredentials {
     /// # Arguments
     ///
     /// * `credentials` - The credentials required for login
-    /// * `region` - The region to use
+    /// * `region` - The region to use, read from the environment if not present
     ///
     /// # Returns
     /// Bucket credentials
     pub fn new(credentials: Credentials, region: Option<String>) -> Self {
         Self {
-            credentials,
-            region: Region::new(region.or_else(|| Some(DEFAULT_REGION.into())).unwrap()),
+            credentials: SharedCredentialsProvider::new(credentials),
+            region: resolve_region(region),
         }
     }
+
+    /// Creates a bucket credential config which loads the credentials like the aws cli, from
+    /// environment variables, the profile files, a web identity token or the container and
+    /// instance metadata in this order
+    ///
+    /// # Arguments
+    ///
+    /// * `region` - The region to use, read from the environment if not present
+    ///
+    /// # Returns
+    /// Bucket credentials
+    pub fn from_provider_chain(region: Option<String>) -> Self {
+        let region = resolve_region(region);
+        let config = ProviderConfig::default().with_region(Some(region.clone()));
+        let chain = CredentialsProviderChain::first_try(
+            "Environment",
+            EnvironmentVariableCredentialsProvider::new(),
+        )
+        .or_else(
+            "Profile",
+            ProfileFileCredentialsProvider::builder()
+                .configure(&config)
+                .build(),
+        )
+        .or_else(
+            "WebIdentityToken",
+            WebIdentityTokenCredentialsProvider::builder()
+                .configure(&config)
+                .build(),
+        )
+        .or_else(
+            "EcsContainer",
+            EcsCredentialsProvider::builder().configure(&config).build(),
+        )
+        .or_else(
+            "Ec2InstanceMetadata",
+            ImdsCredentialsProvider::builder()
+                .configure(&config)
+                .build(),
+        );
+        Self {
+            credentials: SharedCredentialsProvider::new(chain),
+            region,
+        }
+    }
+}
+
+/// Resolves the region from the config, falls back to the `AWS_REGION` and
+/// `AWS_DEFAULT_REGION` environment variables
+fn resolve_region(region: Option<String>) -> Region {
+    let region = region
+        .or_else(|| env::var("AWS_REGION").ok())
+        .or_else(|| env::var("AWS_DEFAULT_REGION").ok())
+        .unwrap_or(DEFAULT_REGION.to_string());
+    Region::new(region)
 }
@@ -0,0 +1,102 @@
+use aws_sdk_s3::types::ServerSideEncryption;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::{Digest, Md5};
+use thiserror::Error;
+
+/// Algorithm used for keys provided by the customer, the only one supported by s3
+const CUSTOMER_KEY_ALGORITHM: &str = "AES256";
+/// Length of keys provided by the customer in bytes
+const CUSTOMER_KEY_LENGTH: usize = 32;
+
+/// Decides how the bucket encrypts stored objects
+#[derive(Debug, Clone)]
+pub enum BucketEncryption {
+    // Keys managed by the storage provider (SSE-S3)
+    S3,
+    // Keys managed by a key management service (SSE-KMS), the default key of the bucket is
+    // used if no key id is present
+    Kms(Option<String>),
+    // Key sent along with every request, the provider doesn't store it (SSE-C)
+    Customer(CustomerKey),
+}
+
+/// Key used for encrypting objects with customer provided keys
+#[derive(Debug, Clone)]
+pub struct CustomerKey {
+    // The base64 encoded key
+    key: String,
+    // The base64 encoded MD5 digest of the key, used by the provider to verify it
+    key_md5: String,
+}
+
+#[derive(Debug, Error)]
+pub enum CustomerKeyError {
+    #[error("Customer key is not valid base64")]
+    InvalidEncoding,
+    #[error("Customer key has to be 256 bits long")]
+    InvalidLength,
+}
+
+impl BucketEncryption {
+    /// Returns the algorithm sent with requests creating objects, customer keys send their
+    /// algorithm separately
+    pub fn algorithm(&self) -> Option<ServerSideEncryption> {
+        match self {
+            Self::S3 => Some(ServerSideEncryption::Aes256),
+            Self::Kms(_) => Some(ServerSideEncryption::AwsKms),
+            Self::Customer(_) => None,
+        }
+    }
+
+    /// Returns the id of the key management service key
+    pub fn kms_key_id(&self) -> Option<String> {
+        match self {
+            Self::Kms(key_id) => key_id.clone(),
+            _ => None,
+        }
+    }
+
+    /// Returns the customer key which has to be sent with every request accessing an object
+    pub fn customer_key(&self) -> Option<&CustomerKey> {
+        match self {
+            Self::Customer(key) => Some(key),
+            _ => None,
+        }
+    }
+}
+
+impl CustomerKey {
+    /// Creates a customer key
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The base64 encoded 256 bit key
+    ///
+    /// # Returns
+    ///
+    /// The customer key or an error if the key is invalid
+    pub fn from_base64(key: &str) -> Result<Self, CustomerKeyError> {
+        let bytes = STANDARD
+            .decode(key)
+            .map_err(|_| CustomerKeyError::InvalidEncoding)?;
+        if bytes.len() != CUSTOMER_KEY_LENGTH {
+            return Err(CustomerKeyError::InvalidLength);
+        }
+        Ok(Self {
+            key: key.to_string(),
+            key_md5: STANDARD.encode(Md5::digest(&bytes)),
+        })
+    }
+
+    pub fn algorithm(&self) -> String {
+        CUSTOMER_KEY_ALGORITHM.to_string()
+    }
+
+    pub fn key(&self) -> String {
+        self.key.clone()
+    }
+
+    pub fn key_md5(&self) -> String {
+        self.key_md5.clone()
+    }
+}
@@ -1,2 +1,3 @@
 pub mod bucket;
 pub mod credentials;
+pub mod encryption;
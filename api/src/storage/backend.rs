@@ -0,0 +1,147 @@
+use std::{collections::HashSet, time::Duration};
+
+use async_trait::async_trait;
+
+use super::driver::{ByteRange, FileMetadata, FileStream, PresignedUpload, StorageResult};
+
+/// Stores the objects of files. Backends only move bytes, encryption is applied on top of
+/// them by the `StorageDriver`
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Saves a file in the backend
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file id
+    /// * `content_type` - The file type
+    /// * `size` - The size of the file in bytes
+    /// * `stream` - The file contents
+    async fn save_file(
+        &self,
+        id: &str,
+        content_type: &str,
+        size: u64,
+        stream: FileStream,
+    ) -> StorageResult<()>;
+
+    /// Gets a file from the backend
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file id
+    /// * `range` - The range of bytes to read, reads the entire file if not present. The end
+    ///   may exceed the file, in which case the file is read until its end
+    ///
+    /// # Returns
+    ///
+    /// The file contents and content type
+    async fn get_file(
+        &self,
+        id: &str,
+        range: Option<ByteRange>,
+    ) -> StorageResult<(FileStream, String)>;
+
+    /// Gets the metadata of a file without reading its contents
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file id
+    ///
+    /// # Returns
+    ///
+    /// The file metadata
+    async fn head_file(&self, id: &str) -> StorageResult<FileMetadata>;
+
+    /// Deletes a file from the backend
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file id
+    async fn delete_file(&self, id: &str) -> StorageResult<()>;
+
+    /// Copies a file within the backend, backends which can't copy files themselves read the
+    /// file and save it again
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The id of the file to copy
+    /// * `target` - The id of the copy
+    async fn copy_file(&self, source: &str, target: &str) -> StorageResult<()> {
+        let metadata = self.head_file(source).await?;
+        let (stream, _) = self.get_file(source, None).await?;
+        self.save_file(target, &metadata.content_type, metadata.size, stream)
+            .await
+    }
+
+    /// Lists the ids of all files stored in the backend
+    async fn list_files(&self) -> StorageResult<Vec<String>>;
+
+    /// Checks whether a file is stored in the backend
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file id
+    async fn file_exists(&self, id: &str) -> StorageResult<bool>;
+
+    /// Checks whether the backend stores multiple copies of every file which may have to be
+    /// restored using `repair`
+    fn has_replicas(&self) -> bool {
+        false
+    }
+
+    /// Restores copies of files which are missing, only does something for backends which
+    /// store multiple copies of every file
+    ///
+    /// # Arguments
+    ///
+    /// * `ids` - The ids of the files which should be stored, other files are never copied
+    ///
+    /// # Returns
+    ///
+    /// The amount of restored copies
+    async fn repair(&self, _ids: &HashSet<String>) -> StorageResult<usize> {
+        Ok(0)
+    }
+
+    /// Creates a short-lived url which allows clients to download a file directly from the
+    /// backend instead of through the api
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file id
+    /// * `content_type` - The content type the file should be served with
+    /// * `content_disposition` - The Content-Disposition header the file should be served with
+    ///
+    /// # Returns
+    ///
+    /// The url or `None` if the backend can't serve files directly
+    async fn presign_download(
+        &self,
+        _id: &str,
+        _content_type: Option<&str>,
+        _content_disposition: &str,
+    ) -> StorageResult<Option<String>> {
+        Ok(None)
+    }
+
+    /// Creates a short-lived url which allows clients to upload a file directly to the
+    /// backend instead of through the api
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file id
+    /// * `content_type` - The content type the file has to be uploaded with
+    /// * `expiration` - The time the url is valid for
+    ///
+    /// # Returns
+    ///
+    /// The url and required headers or `None` if the backend can't receive files directly
+    async fn presign_upload(
+        &self,
+        _id: &str,
+        _content_type: &str,
+        _expiration: Duration,
+    ) -> StorageResult<Option<PresignedUpload>> {
+        Ok(None)
+    }
+}
@@ -1,68 +1,720 @@
 use std::{
-    fs::File,
-    io::{Read, Write},
-    path::PathBuf,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use super::driver::{StorageError, StorageResult};
+use async_trait::async_trait;
+use rocket::tokio::{
+    fs::{self, File},
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::{
+    backend::StorageBackend,
+    driver::{ByteRange, FileMetadata, FileStream, StorageError, StorageResult},
+};
+
+/// Marks files using the versioned header, legacy (v1) files instead start with the length of
+/// their content type
+const HEADER_MAGIC: &[u8; 4] = b"UPLD";
+/// Version of the header written for new files
+const FORMAT_VERSION: u8 = 2;
+/// Size of the buffer used when copying file contents
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+/// Amount of directory levels files are stored in if not configured
+pub const DEFAULT_SHARD_DEPTH: usize = 2;
+/// Maximum amount of directory levels, every level uses two characters of the id hash
+const MAX_SHARD_DEPTH: usize = 8;
 
 macro_rules! try_write {
     ($f:expr, $i:expr) => {
-        $f.write($i).map_err(|err| StorageError::from(err))?
+        $f.write_all($i).await.map_err(StorageError::from)?
+    };
+}
+
+macro_rules! try_read {
+    ($f:expr, $i:expr) => {
+        $f.read_exact($i)
+            .await
+            .map_err(|_| StorageError::DriveLoadError)?
     };
 }
 
+/// Metadata stored in front of the contents of a file
+///
+/// Layout of version 2 (integers are big endian):
+/// `"UPLD"`, version (u8), content type length (u16), content type, size (u64),
+/// SHA-256 of the contents (32 bytes), creation time in ms since the unix epoch (u64)
+#[derive(Debug, Clone)]
+pub struct DriveHeader {
+    pub version: u8,
+    pub content_type: String,
+    // Size of the contents in bytes, not stored by v1 files
+    pub size: Option<u64>,
+    // SHA-256 of the contents, not stored by v1 files
+    pub hash: Option<[u8; 32]>,
+    // Creation time in ms since the unix epoch, not stored by v1 files
+    pub created_at: Option<u64>,
+}
+
+/// Decides where files are placed within the storage directory. Files are spread across
+/// subdirectories named after the hash of their id (e.g. `ab/cd/<id>`) so no directory
+/// grows too large
+#[derive(Debug, Clone)]
+pub struct DriveLayout {
+    root: PathBuf,
+    shard_depth: usize,
+}
+
+impl DriveLayout {
+    pub fn new(root: PathBuf, shard_depth: usize) -> Self {
+        Self {
+            root,
+            shard_depth: shard_depth.min(MAX_SHARD_DEPTH),
+        }
+    }
+
+    /// Returns the storage directory
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns the path a file is stored at
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file id
+    pub fn file_path(&self, id: &str) -> PathBuf {
+        let hash = hex::encode(Sha256::digest(id.as_bytes()));
+        let mut path = self.root.clone();
+        for level in 0..self.shard_depth {
+            path.push(&hash[level * 2..level * 2 + 2]);
+        }
+        path.push(id);
+        path
+    }
+
+    /// Finds the path of an existing file, files stored before sharding was introduced are
+    /// placed directly in the storage directory
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file id
+    pub async fn find_file(&self, id: &str) -> PathBuf {
+        let path = self.file_path(id);
+        if self.shard_depth == 0 || fs::try_exists(&path).await.unwrap_or(false) {
+            return path;
+        }
+        let legacy_path = self.root.join(id);
+        if fs::try_exists(&legacy_path).await.unwrap_or(false) {
+            legacy_path
+        } else {
+            path
+        }
+    }
+}
+
+/// Stores files in a directory of the local file system
+#[derive(Debug, Clone)]
+pub struct DriveBackend {
+    layout: DriveLayout,
+}
+
+impl DriveBackend {
+    pub fn new(layout: DriveLayout) -> Self {
+        Self { layout }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for DriveBackend {
+    async fn save_file(
+        &self,
+        id: &str,
+        content_type: &str,
+        _size: u64,
+        stream: FileStream,
+    ) -> StorageResult<()> {
+        save_file(&self.layout, id, content_type, stream).await
+    }
+
+    async fn get_file(
+        &self,
+        id: &str,
+        range: Option<ByteRange>,
+    ) -> StorageResult<(FileStream, String)> {
+        get_file(&self.layout, id, range).await
+    }
+
+    async fn head_file(&self, id: &str) -> StorageResult<FileMetadata> {
+        head_file(&self.layout, id).await
+    }
+
+    async fn delete_file(&self, id: &str) -> StorageResult<()> {
+        delete_file(&self.layout, id).await
+    }
+
+    async fn list_files(&self) -> StorageResult<Vec<String>> {
+        list_files(&self.layout).await
+    }
+
+    async fn file_exists(&self, id: &str) -> StorageResult<bool> {
+        fs::try_exists(self.layout.find_file(id).await)
+            .await
+            .map_err(StorageError::from)
+    }
+}
+
 /// Implements save_file for the Drive type
 pub(crate) async fn save_file(
-    root: &PathBuf,
+    layout: &DriveLayout,
     id: &str,
     content_type: &str,
-    bytes: Vec<u8>,
+    mut stream: FileStream,
 ) -> StorageResult<()> {
-    let mut file_path = root.clone();
-    file_path.push(id);
+    let file_path = layout.file_path(id);
 
-    std::fs::create_dir_all(root).map_err(|err| StorageError::from(err))?;
-    let mut file = File::create(file_path).map_err(|err| StorageError::from(err))?;
-    try_write!(file, &[content_type.len() as u8]);
-    try_write!(file, content_type.as_bytes());
-    try_write!(file, &bytes);
-    Ok(())
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .map_err(StorageError::from)?;
+    }
+    write_file_atomically(&file_path, content_type, now_in_ms(), &mut stream).await
 }
 
 /// Implements get_file for the Drive type
-pub(crate) async fn get_file(root: &PathBuf, id: &str) -> StorageResult<(Vec<u8>, String)> {
-    let mut file_path = root.clone();
-    file_path.push(id);
+pub(crate) async fn get_file(
+    layout: &DriveLayout,
+    id: &str,
+    range: Option<ByteRange>,
+) -> StorageResult<(FileStream, String)> {
+    let file_path = layout.find_file(id).await;
 
-    let mut file = File::open(file_path).map_err(|_| StorageError::DriveLoadError)?;
-    let content_type = read_content_type(&mut file)?;
-    let bytes = read_file_bytes(&mut file)?;
-    Ok((bytes, content_type))
+    let mut file = File::open(file_path)
+        .await
+        .map_err(|_| StorageError::DriveLoadError)?;
+    let header = read_header(&mut file).await?;
+    // The cursor is now placed right after the header, the rest of the file is the content
+    match range {
+        Some(range) => {
+            file.seek(SeekFrom::Current(range.start as i64))
+                .await
+                .map_err(|_| StorageError::DriveLoadError)?;
+            Ok((Box::pin(file.take(range.size())), header.content_type))
+        }
+        None => Ok((Box::pin(file), header.content_type)),
+    }
+}
+
+/// Implements head_file for the Drive type
+pub(crate) async fn head_file(layout: &DriveLayout, id: &str) -> StorageResult<FileMetadata> {
+    let file_path = layout.find_file(id).await;
+
+    let mut file = File::open(file_path)
+        .await
+        .map_err(|_| StorageError::DriveLoadError)?;
+    let header = read_header(&mut file).await?;
+    let size = match header.size {
+        Some(size) => size,
+        None => {
+            let header_size = file
+                .stream_position()
+                .await
+                .map_err(|_| StorageError::DriveLoadError)?;
+            let file_size = file
+                .metadata()
+                .await
+                .map_err(|_| StorageError::DriveLoadError)?
+                .len();
+            file_size - header_size
+        }
+    };
+    Ok(FileMetadata {
+        content_type: header.content_type,
+        size,
+    })
 }
 
 /// Implements delete_file for the Drive type
-pub(crate) async fn delete_file(root: &PathBuf, id: &str) -> StorageResult<()> {
-    let mut file_path = root.clone();
-    file_path.push(id);
-    std::fs::remove_file(file_path).map_err(|_| StorageError::DriveDeleteError)
+pub(crate) async fn delete_file(layout: &DriveLayout, id: &str) -> StorageResult<()> {
+    let file_path = layout.find_file(id).await;
+    fs::remove_file(file_path)
+        .await
+        .map_err(|_| StorageError::DriveDeleteError)
 }
 
-/// Reads the content type stored in the file
-fn read_content_type(file: &mut File) -> StorageResult<String> {
-    let mut ct_len = [0 as u8];
-    file.read_exact(&mut ct_len)
-        .map_err(|_| StorageError::DriveLoadError)?;
-    let mut ct = vec![0 as u8; ct_len[0] as usize];
-    file.read_exact(&mut ct)
-        .map_err(|_| StorageError::DriveLoadError)?;
-    String::from_utf8(ct).map_err(|_| StorageError::DriveLoadError)
+/// Lists the ids of all stored files, including files which are not placed in their shard
+///
+/// # Arguments
+///
+/// * `layout` - The storage layout
+pub async fn list_files(layout: &DriveLayout) -> StorageResult<Vec<String>> {
+    let mut directories = vec![layout.root().to_path_buf()];
+    let mut ids = Vec::new();
+    while let Some(directory) = directories.pop() {
+        let mut entries = fs::read_dir(directory).await.map_err(StorageError::from)?;
+        while let Some(entry) = entries.next_entry().await.map_err(StorageError::from)? {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let kind = entry.file_type().await.map_err(StorageError::from)?;
+            if kind.is_dir() {
+                directories.push(entry.path());
+            } else if kind.is_file() && !name.starts_with('.') {
+                // Hidden files are temporary files which are still being written
+                ids.push(name);
+            }
+        }
+    }
+    Ok(ids)
 }
 
-/// Reads the file bytes stored in the file
-fn read_file_bytes(file: &mut File) -> StorageResult<Vec<u8>> {
-    let mut bytes = Vec::new();
-    file.read_to_end(&mut bytes)
+/// Rewrites a file using the current header format and moves it into its shard. The file is
+/// replaced atomically so it can be served while being upgraded
+///
+/// # Arguments
+///
+/// * `layout` - The storage layout
+/// * `id` - The file id
+///
+/// # Returns
+///
+/// Whether the file had to be upgraded or moved
+pub async fn upgrade_file(layout: &DriveLayout, id: &str) -> StorageResult<bool> {
+    let file_path = layout.find_file(id).await;
+    let target_path = layout.file_path(id);
+
+    let mut file = File::open(&file_path)
+        .await
         .map_err(|_| StorageError::DriveLoadError)?;
-    Ok(bytes)
+    let header = read_header(&mut file).await?;
+    if header.version >= FORMAT_VERSION && file_path == target_path {
+        return Ok(false);
+    }
+    if let Some(parent) = target_path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .map_err(StorageError::from)?;
+    }
+    if header.version >= FORMAT_VERSION {
+        fs::rename(&file_path, &target_path)
+            .await
+            .map_err(StorageError::from)?;
+        return Ok(true);
+    }
+
+    // Legacy files don't know when they were created, the modification time is the closest
+    let created_at = file
+        .metadata()
+        .await
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or_else(now_in_ms, |duration| duration.as_millis() as u64);
+
+    write_file_atomically(&target_path, &header.content_type, created_at, &mut file).await?;
+    // The upgraded file is already found in its shard, the legacy file is no longer used
+    if file_path != target_path {
+        fs::remove_file(&file_path)
+            .await
+            .map_err(StorageError::from)?;
+    }
+    Ok(true)
+}
+
+/// Writes a file to a hidden temporary file first and moves it to its path once it has been
+/// written completely, so a crash never leaves a truncated file behind which would be served.
+/// Every write uses its own temporary file, concurrent writes of the same file can't mix
+///
+/// # Arguments
+///
+/// * `path` - The path of the file
+/// * `content_type` - The file type
+/// * `created_at` - The creation time in ms since the unix epoch
+/// * `stream` - The file contents
+async fn write_file_atomically<R: AsyncRead + Unpin>(
+    path: &Path,
+    content_type: &str,
+    created_at: u64,
+    stream: &mut R,
+) -> StorageResult<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, Uuid::new_v4().simple()));
+    let result = async {
+        let mut file = File::create(&temp_path).await.map_err(StorageError::from)?;
+        write_file(&mut file, content_type, created_at, stream).await?;
+        file.sync_all().await.map_err(StorageError::from)?;
+        fs::rename(&temp_path, path)
+            .await
+            .map_err(StorageError::from)
+    }
+    .await;
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path).await;
+    }
+    result
+}
+
+/// Writes the header and contents of a file, the size and hash are filled in once the
+/// contents have been written
+///
+/// # Arguments
+///
+/// * `file` - The file to write to, it has to be empty
+/// * `content_type` - The file type
+/// * `created_at` - The creation time in ms since the unix epoch
+/// * `stream` - The file contents
+async fn write_file<R: AsyncRead + Unpin>(
+    file: &mut File,
+    content_type: &str,
+    created_at: u64,
+    stream: &mut R,
+) -> StorageResult<()> {
+    let content_type_length = u16::try_from(content_type.len()).map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "Content type is too long")
+    })?;
+    try_write!(file, HEADER_MAGIC);
+    try_write!(file, &[FORMAT_VERSION]);
+    try_write!(file, &content_type_length.to_be_bytes());
+    try_write!(file, content_type.as_bytes());
+    let checksum_position = file.stream_position().await.map_err(StorageError::from)?;
+    try_write!(file, &0_u64.to_be_bytes());
+    try_write!(file, &[0_u8; 32]);
+    try_write!(file, &created_at.to_be_bytes());
+
+    let mut hasher = Sha256::new();
+    let mut size = 0_u64;
+    let mut buffer = vec![0_u8; COPY_BUFFER_SIZE];
+    loop {
+        let read = stream.read(&mut buffer).await.map_err(StorageError::from)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        try_write!(file, &buffer[..read]);
+        size += read as u64;
+    }
+
+    file.seek(SeekFrom::Start(checksum_position))
+        .await
+        .map_err(StorageError::from)?;
+    try_write!(file, &size.to_be_bytes());
+    try_write!(file, &hasher.finalize());
+    file.flush().await.map_err(StorageError::from)?;
+    Ok(())
+}
+
+/// Reads the header of a file, the cursor is placed at the start of the contents afterwards
+async fn read_header(file: &mut File) -> StorageResult<DriveHeader> {
+    let mut first = [0_u8];
+    try_read!(file, &mut first);
+    if first[0] == HEADER_MAGIC[0] {
+        let mut magic = [0_u8; 3];
+        if file.read_exact(&mut magic).await.is_ok() && magic == HEADER_MAGIC[1..] {
+            let mut version = [0_u8];
+            try_read!(file, &mut version);
+            // Content types are printable, so legacy files starting with the magic bytes are
+            // told apart by their fifth byte
+            if !version[0].is_ascii_graphic() {
+                return read_versioned_header(file, version[0]).await;
+            }
+        }
+        // Legacy file with a content type of the same length as the first magic byte
+        file.seek(SeekFrom::Start(1))
+            .await
+            .map_err(|_| StorageError::DriveLoadError)?;
+    }
+
+    let mut content_type = vec![0_u8; first[0] as usize];
+    try_read!(file, &mut content_type);
+    Ok(DriveHeader {
+        version: 1,
+        content_type: String::from_utf8(content_type).map_err(|_| StorageError::DriveLoadError)?,
+        size: None,
+        hash: None,
+        created_at: None,
+    })
+}
+
+/// Reads the part of the header following the magic bytes and version
+async fn read_versioned_header(file: &mut File, version: u8) -> StorageResult<DriveHeader> {
+    if version > FORMAT_VERSION {
+        return Err(StorageError::DriveLoadError);
+    }
+
+    let mut content_type_length = [0_u8; 2];
+    try_read!(file, &mut content_type_length);
+    let mut content_type = vec![0_u8; u16::from_be_bytes(content_type_length) as usize];
+    try_read!(file, &mut content_type);
+    let mut size = [0_u8; 8];
+    try_read!(file, &mut size);
+    let mut hash = [0_u8; 32];
+    try_read!(file, &mut hash);
+    let mut created_at = [0_u8; 8];
+    try_read!(file, &mut created_at);
+
+    Ok(DriveHeader {
+        version,
+        content_type: String::from_utf8(content_type).map_err(|_| StorageError::DriveLoadError)?,
+        size: Some(u64::from_be_bytes(size)),
+        hash: Some(hash),
+        created_at: Some(u64::from_be_bytes(created_at)),
+    })
+}
+
+/// Returns the current time in ms since the unix epoch
+fn now_in_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// Storage directory which is removed once the test ends
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!("uploader-drive-{}", Uuid::new_v4().simple())))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn stream(data: &[u8]) -> FileStream {
+        Box::pin(Cursor::new(data.to_vec()))
+    }
+
+    async fn read(layout: &DriveLayout, id: &str, range: Option<ByteRange>) -> (Vec<u8>, String) {
+        let (mut stream, content_type) = get_file(layout, id, range)
+            .await
+            .expect("file should exist");
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).await.unwrap();
+        (data, content_type)
+    }
+
+    async fn header(path: &Path) -> StorageResult<DriveHeader> {
+        read_header(&mut File::open(path).await.unwrap()).await
+    }
+
+    /// Writes a file in the legacy format, which only stores the content type
+    async fn write_legacy(path: &Path, content_type: &str, data: &[u8]) {
+        let mut bytes = vec![content_type.len() as u8];
+        bytes.extend_from_slice(content_type.as_bytes());
+        bytes.extend_from_slice(data);
+        fs::create_dir_all(path.parent().unwrap()).await.unwrap();
+        fs::write(path, bytes).await.unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn writes_versioned_header() {
+        let dir = TempDir::new();
+        let layout = DriveLayout::new(dir.0.clone(), 0);
+        save_file(&layout, "a", "text/plain", stream(b"hello"))
+            .await
+            .unwrap();
+
+        let header = header(&layout.file_path("a")).await.unwrap();
+        assert_eq!(header.version, FORMAT_VERSION);
+        assert_eq!(header.content_type, "text/plain");
+        assert_eq!(header.size, Some(5));
+        assert_eq!(header.hash, Some(Sha256::digest(b"hello").into()));
+        assert!(header.created_at.is_some());
+        assert_eq!(
+            read(&layout, "a", None).await,
+            (b"hello".to_vec(), "text/plain".into())
+        );
+        assert_eq!(
+            read(&layout, "a", Some(ByteRange::new(1, 3))).await.0,
+            b"ell"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn reads_legacy_files() {
+        let dir = TempDir::new();
+        let layout = DriveLayout::new(dir.0.clone(), 0);
+        write_legacy(&layout.file_path("a"), "image/png", b"contents").await;
+
+        let header = header(&layout.file_path("a")).await.unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(header.content_type, "image/png");
+        assert_eq!(header.size, None);
+        assert_eq!(head_file(&layout, "a").await.unwrap().size, 8);
+        assert_eq!(
+            read(&layout, "a", Some(ByteRange::new(4, 100))).await,
+            (b"ents".to_vec(), "image/png".into())
+        );
+    }
+
+    #[rocket::async_test]
+    async fn reads_legacy_files_starting_like_the_magic_bytes() {
+        let dir = TempDir::new();
+        let layout = DriveLayout::new(dir.0.clone(), 0);
+        // The length 85 is stored as 'U', the first magic byte
+        for content_type in [
+            format!("application/{}", "x".repeat(73)),
+            format!("PLDapplication/{}", "x".repeat(70)),
+        ] {
+            assert_eq!(content_type.len(), 85);
+            write_legacy(&layout.file_path("a"), &content_type, b"contents").await;
+            assert_eq!(
+                read(&layout, "a", None).await,
+                (b"contents".to_vec(), content_type)
+            );
+        }
+    }
+
+    #[rocket::async_test]
+    async fn rejects_unknown_versions() {
+        let dir = TempDir::new();
+        let path = dir.0.join("a");
+        fs::create_dir_all(&dir.0).await.unwrap();
+        let mut bytes = HEADER_MAGIC.to_vec();
+        bytes.push(FORMAT_VERSION + 1);
+        bytes.extend_from_slice(&[0; 64]);
+        fs::write(&path, bytes).await.unwrap();
+        assert!(header(&path).await.is_err());
+    }
+
+    #[rocket::async_test]
+    async fn upgrades_legacy_files() {
+        let dir = TempDir::new();
+        let layout = DriveLayout::new(dir.0.clone(), 0);
+        write_legacy(&layout.file_path("a"), "text/plain", b"hello").await;
+
+        assert!(upgrade_file(&layout, "a").await.unwrap());
+        let header = header(&layout.file_path("a")).await.unwrap();
+        assert_eq!(header.version, FORMAT_VERSION);
+        assert_eq!(header.size, Some(5));
+        assert_eq!(read(&layout, "a", None).await.0, b"hello");
+        assert!(!upgrade_file(&layout, "a").await.unwrap());
+    }
+
+    #[test]
+    fn places_files_in_shards() {
+        let layout = DriveLayout::new(PathBuf::from("/data"), 2);
+        let hash = hex::encode(Sha256::digest(b"a"));
+        assert_eq!(
+            layout.file_path("a"),
+            PathBuf::from(format!("/data/{}/{}/a", &hash[..2], &hash[2..4]))
+        );
+        assert_eq!(
+            DriveLayout::new(PathBuf::from("/data"), 0).file_path("a"),
+            PathBuf::from("/data/a")
+        );
+        assert_eq!(
+            DriveLayout::new(PathBuf::from("/data"), 100)
+                .file_path("a")
+                .components()
+                .count(),
+            // Root, the directory, the maximum amount of levels and the file
+            MAX_SHARD_DEPTH + 3
+        );
+    }
+
+    #[rocket::async_test]
+    async fn finds_files_stored_before_sharding() {
+        let dir = TempDir::new();
+        let layout = DriveLayout::new(dir.0.clone(), 2);
+        write_legacy(&dir.0.join("a"), "text/plain", b"hello").await;
+
+        assert_eq!(layout.find_file("a").await, dir.0.join("a"));
+        assert_eq!(read(&layout, "a", None).await.0, b"hello");
+        // Missing files are looked up in their shard
+        assert_eq!(layout.find_file("b").await, layout.file_path("b"));
+
+        assert!(upgrade_file(&layout, "a").await.unwrap());
+        assert_eq!(layout.find_file("a").await, layout.file_path("a"));
+        assert!(!fs::try_exists(dir.0.join("a")).await.unwrap());
+        assert_eq!(read(&layout, "a", None).await.0, b"hello");
+    }
+
+    #[rocket::async_test]
+    async fn lists_sharded_and_legacy_files() {
+        let dir = TempDir::new();
+        let layout = DriveLayout::new(dir.0.clone(), 2);
+        write_legacy(&dir.0.join("a"), "text/plain", b"hello").await;
+        save_file(&layout, "b", "text/plain", stream(b"hello"))
+            .await
+            .unwrap();
+
+        let mut ids = list_files(&layout).await.unwrap();
+        ids.sort();
+        assert_eq!(ids, vec!["a", "b"]);
+        delete_file(&layout, "a").await.unwrap();
+        delete_file(&layout, "b").await.unwrap();
+        assert!(list_files(&layout).await.unwrap().is_empty());
+    }
+
+    /// Stream which fails after its contents, like an upload which was aborted
+    struct FailingStream(Cursor<Vec<u8>>);
+
+    impl AsyncRead for FailingStream {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut rocket::tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            if self.0.position() == self.0.get_ref().len() as u64 {
+                return std::task::Poll::Ready(Err(std::io::ErrorKind::ConnectionReset.into()));
+            }
+            std::pin::Pin::new(&mut self.0).poll_read(cx, buf)
+        }
+    }
+
+    async fn directory_entries(path: &Path) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut entries = fs::read_dir(path).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+        names
+    }
+
+    #[rocket::async_test]
+    async fn keeps_file_if_write_fails() {
+        let dir = TempDir::new();
+        let layout = DriveLayout::new(dir.0.clone(), 0);
+        save_file(&layout, "a", "text/plain", stream(b"hello"))
+            .await
+            .unwrap();
+
+        let failing = Box::pin(FailingStream(Cursor::new(b"bye".to_vec())));
+        assert!(save_file(&layout, "a", "text/plain", failing)
+            .await
+            .is_err());
+        assert_eq!(read(&layout, "a", None).await.0, b"hello");
+        // The temporary file of the failed write is removed
+        assert_eq!(directory_entries(&dir.0).await, vec!["a"]);
+    }
+
+    #[rocket::async_test]
+    async fn writes_same_file_concurrently() {
+        let dir = TempDir::new();
+        let layout = DriveLayout::new(dir.0.clone(), 0);
+        let data: Vec<Vec<u8>> = (0..8_u8).map(|i| vec![i; 256 * 1024]).collect();
+        let writes = data
+            .iter()
+            .map(|data| save_file(&layout, "a", "text/plain", stream(data)));
+        for result in futures_util::future::join_all(writes).await {
+            result.unwrap();
+        }
+
+        // One of the writes wins entirely, the contents are never mixed
+        let (contents, _) = read(&layout, "a", None).await;
+        assert!(data.contains(&contents));
+        assert_eq!(directory_entries(&dir.0).await, vec!["a"]);
+    }
 }
@@ -1,17 +1,76 @@
-use std::path::PathBuf;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, io,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
 
+use log::error;
+use rocket::tokio::{
+    self,
+    io::{AsyncRead, ReadBuf},
+};
 use thiserror::Error;
 
-use crate::s3::bucket::Bucket;
-
-use super::{drive, object_storage};
+use super::{
+    backend::StorageBackend,
+    encryption::{self, DataKey, DecryptingStream, EncryptingStream},
+};
 
 pub type StorageResult<T> = std::result::Result<T, StorageError>;
 
+/// Async byte stream used to move file contents in and out of storage drivers without buffering
+pub type FileStream = Pin<Box<dyn AsyncRead + Send + Sync>>;
+
+/// Inclusive range of bytes within the content of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+/// File stream which deletes the file from the storage driver once it has been dropped,
+/// used to serve files a last time before they are gone
+pub struct DeleteOnDropStream {
+    inner: FileStream,
+    driver: StorageDriver,
+    id: String,
+}
+
+/// Stores information about a file without its contents
+#[derive(Debug, Clone)]
+pub struct FileMetadata {
+    pub content_type: String,
+    pub size: u64,
+}
+
+/// Url which allows clients to upload a file directly to the storage
 #[derive(Debug, Clone)]
-pub enum StorageDriver {
-    ObjectStorage { bucket: Bucket },
-    Drive { path: PathBuf },
+pub struct PresignedUpload {
+    pub url: String,
+    // Headers the client has to send along with the upload, they are part of the signature
+    pub headers: HashMap<String, String>,
+}
+
+/// Tier a file is stored in, recently used files are kept in the hot tier while unused
+/// files are moved to the cheaper cold tier
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StorageTier {
+    #[default]
+    Hot,
+    Cold,
+}
+
+/// Provides access to the configured storage backends, encrypting files if requested
+#[derive(Clone)]
+pub struct StorageDriver {
+    hot: Arc<dyn StorageBackend>,
+    cold: Option<Arc<dyn StorageBackend>>,
+    // Tier files are saved in and read from first
+    tier: StorageTier,
 }
 
 #[derive(Debug, Error)]
@@ -28,15 +87,160 @@ pub enum StorageError {
     DriveLoadError,
     #[error("Failed to delete file from drive")]
     DriveDeleteError,
+    #[error("Failed to load file from memory")]
+    MemoryLoadError,
+    #[error("Failed to delete file from memory")]
+    MemoryDeleteError,
+    #[error("The file exceeds the capacity of the memory storage")]
+    MemoryFullError,
+    #[error("Not enough replicas succeeded")]
+    ReplicationError,
+    #[error("The configured master key is invalid")]
+    InvalidMasterKeyError,
+    #[error("The master key the file was encrypted with is not configured")]
+    UnknownMasterKeyError,
+    #[error("Failed to encrypt or decrypt file key")]
+    EncryptionError,
+    // Lets backends added by other crates report their own errors
+    #[error("Storage backend failed ({0})")]
+    BackendError(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl ByteRange {
+    pub fn new(start: u64, end: u64) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns the amount of bytes in the range
+    pub fn size(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+impl StorageTier {
+    /// Returns the name the tier is stored as in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageTier::Hot => "hot",
+            StorageTier::Cold => "cold",
+        }
+    }
+}
+
+impl FromStr for StorageTier {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hot" => Ok(StorageTier::Hot),
+            "cold" => Ok(StorageTier::Cold),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for StorageTier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl DeleteOnDropStream {
+    pub fn new(inner: FileStream, driver: StorageDriver, id: String) -> Self {
+        Self { inner, driver, id }
+    }
+}
+
+impl AsyncRead for DeleteOnDropStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.inner.as_mut().poll_read(cx, buf)
+    }
+}
+
+impl Drop for DeleteOnDropStream {
+    fn drop(&mut self) {
+        let driver = self.driver.clone();
+        let id = std::mem::take(&mut self.id);
+        tokio::spawn(async move {
+            if let Err(err) = driver.delete_file(&id).await {
+                error!("Failed to delete file {} after its last view: {}", id, err);
+            }
+        });
+    }
 }
 
 impl StorageDriver {
-    pub fn object(bucket: Bucket) -> Self {
-        Self::ObjectStorage { bucket }
+    pub fn new(backend: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            hot: backend,
+            cold: None,
+            tier: StorageTier::Hot,
+        }
+    }
+
+    /// Adds a cold tier which unused files are moved to
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The backend of the cold tier
+    pub fn with_cold_tier(mut self, backend: Arc<dyn StorageBackend>) -> Self {
+        self.cold = Some(backend);
+        self
+    }
+
+    /// Checks whether a cold tier is configured
+    pub fn has_cold_tier(&self) -> bool {
+        self.cold.is_some()
+    }
+
+    /// Returns a driver accessing the files of the given tier, files are read from the other
+    /// tier if they are missing as they may just have been moved.
+    /// Without a cold tier every tier is stored in the main backend
+    ///
+    /// # Arguments
+    ///
+    /// * `tier` - The tier the files are stored in
+    pub fn tier(&self, tier: StorageTier) -> StorageDriver {
+        Self {
+            tier,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the backend files are stored in
+    pub fn backend(&self) -> &Arc<dyn StorageBackend> {
+        match (self.tier, &self.cold) {
+            (StorageTier::Cold, Some(cold)) => cold,
+            _ => &self.hot,
+        }
+    }
+
+    /// Returns the backend of the other tier if a cold tier is configured
+    fn fallback_backend(&self) -> Option<&Arc<dyn StorageBackend>> {
+        match (self.tier, &self.cold) {
+            (StorageTier::Hot, Some(cold)) => Some(cold),
+            (StorageTier::Cold, Some(_)) => Some(&self.hot),
+            _ => None,
+        }
     }
 
-    pub fn drive(path: PathBuf) -> Self {
-        Self::Drive { path }
+    /// Reads a file from the backend of the tier, falls back to the other tier if it fails
+    async fn read_backend(
+        &self,
+        id: &str,
+        range: Option<ByteRange>,
+    ) -> StorageResult<(FileStream, String)> {
+        match (
+            self.backend().get_file(id, range).await,
+            self.fallback_backend(),
+        ) {
+            (Err(_), Some(fallback)) => fallback.get_file(id, range).await,
+            (result, _) => result,
+        }
     }
 
     /// Saves a file in the storage driver
@@ -45,22 +249,61 @@ impl StorageDriver {
     ///
     /// * `id` - The file id
     /// * `content_type` - The file type
-    /// * `bytes` - The file bytes
+    /// * `size` - The size of the file in bytes
+    /// * `stream` - The file contents
+    /// * `key` - The data key the contents are encrypted with, stored as is if not present
     pub async fn save_file(
         &self,
         id: &str,
         content_type: &str,
-        bytes: Vec<u8>,
+        size: u64,
+        stream: FileStream,
+        key: Option<&DataKey>,
     ) -> StorageResult<()> {
-        match self {
-            Self::ObjectStorage { bucket } => {
-                object_storage::save_file(bucket, id, content_type, bytes).await
+        let (size, stream): (u64, FileStream) = match key {
+            Some(key) => (
+                encryption::encrypted_size(size),
+                Box::pin(EncryptingStream::new(stream, key)),
+            ),
+            None => (size, stream),
+        };
+        self.backend()
+            .save_file(id, content_type, size, stream)
+            .await
+    }
+
+    /// Gets a file from the storage driver
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file id
+    /// * `range` - The range of bytes to read, reads the entire file if not present
+    /// * `key` - The data key the contents are encrypted with, read as is if not present
+    ///
+    /// # Returns
+    ///
+    /// The file contents and content type
+    pub async fn get_file(
+        &self,
+        id: &str,
+        range: Option<ByteRange>,
+        key: Option<&DataKey>,
+    ) -> StorageResult<(FileStream, String)> {
+        match key {
+            Some(key) => {
+                let (stream, content_type) = self
+                    .read_backend(id, range.map(encryption::encrypted_range))
+                    .await?;
+                Ok((
+                    Box::pin(DecryptingStream::new(stream, key, range)),
+                    content_type,
+                ))
             }
-            Self::Drive { path } => drive::save_file(path, id, content_type, bytes).await,
+            None => self.read_backend(id, range).await,
         }
     }
 
-    /// Gets a file from the storage driver
+    /// Gets the metadata of a file from the storage driver without reading its contents
     ///
     /// # Arguments
     ///
@@ -68,11 +311,11 @@ impl StorageDriver {
     ///
     /// # Returns
     ///
-    /// The file bytes and content type
-    pub async fn get_file(&self, id: &str) -> StorageResult<(Vec<u8>, String)> {
-        match self {
-            Self::ObjectStorage { bucket } => object_storage::get_file(bucket, id).await,
-            Self::Drive { path } => drive::get_file(path, id).await,
+    /// The file metadata
+    pub async fn head_file(&self, id: &str) -> StorageResult<FileMetadata> {
+        match (self.backend().head_file(id).await, self.fallback_backend()) {
+            (Err(_), Some(fallback)) => fallback.head_file(id).await,
+            (result, _) => result,
         }
     }
 
@@ -82,9 +325,94 @@ impl StorageDriver {
     ///
     /// + `id` - The file id
     pub async fn delete_file(&self, id: &str) -> StorageResult<()> {
-        match self {
-            Self::ObjectStorage { bucket } => object_storage::delete_file(bucket, id).await,
-            Self::Drive { path } => drive::delete_file(path, id).await,
-        }
+        self.backend().delete_file(id).await
+    }
+
+    /// Copies a file within the tier of the storage driver, the contents are copied as stored
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The id of the file to copy
+    /// * `target` - The id of the copy
+    pub async fn copy_file(&self, source: &str, target: &str) -> StorageResult<()> {
+        self.backend().copy_file(source, target).await
+    }
+
+    /// Lists the ids of all files stored in the tier of the storage driver
+    pub async fn list_files(&self) -> StorageResult<Vec<String>> {
+        self.backend().list_files().await
+    }
+
+    /// Checks whether a file is stored in the storage driver
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file id
+    pub async fn file_exists(&self, id: &str) -> StorageResult<bool> {
+        self.backend().file_exists(id).await
+    }
+
+    /// Creates a short-lived url which allows clients to download a file directly from the
+    /// storage, files stored encrypted can't be served this way
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file id
+    /// * `content_type` - The content type the file should be served with
+    /// * `content_disposition` - The Content-Disposition header the file should be served with
+    ///
+    /// # Returns
+    ///
+    /// The url or `None` if the backend can't serve files directly
+    pub async fn presign_download(
+        &self,
+        id: &str,
+        content_type: Option<&str>,
+        content_disposition: &str,
+    ) -> StorageResult<Option<String>> {
+        self.backend()
+            .presign_download(id, content_type, content_disposition)
+            .await
+    }
+
+    /// Creates a short-lived url which allows clients to upload a file directly to the
+    /// storage, files uploaded this way are stored unencrypted
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The file id
+    /// * `content_type` - The content type the file has to be uploaded with
+    /// * `expiration` - The time the url is valid for
+    ///
+    /// # Returns
+    ///
+    /// The url and required headers or `None` if the backend can't receive files directly
+    pub async fn presign_upload(
+        &self,
+        id: &str,
+        content_type: &str,
+        expiration: Duration,
+    ) -> StorageResult<Option<PresignedUpload>> {
+        self.backend()
+            .presign_upload(id, content_type, expiration)
+            .await
+    }
+
+    /// Checks whether the tier of the storage driver stores multiple copies of every file
+    pub fn has_replicas(&self) -> bool {
+        self.backend().has_replicas()
+    }
+
+    /// Restores missing copies of files in the tier of replicated storage drivers
+    ///
+    /// # Arguments
+    ///
+    /// * `ids` - The ids of the files which should be stored, other files are never copied
+    ///
+    /// # Returns
+    ///
+    /// The amount of restored copies
+    pub async fn repair(&self, ids: &HashSet<String>) -> StorageResult<usize> {
+        self.backend().repair(ids).await
     }
 }
@@ -0,0 +1,555 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use rocket::tokio::io::{AsyncRead, ReadBuf};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::driver::{ByteRange, FileStream, StorageError, StorageResult};
+
+/// Amount of plaintext bytes encrypted as one chunk, chunks are encrypted separately so
+/// files can be streamed and read partially
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Size of the authentication tag appended to every chunk
+const TAG_SIZE: usize = 16;
+/// Size of an encrypted chunk
+const ENCRYPTED_CHUNK_SIZE: usize = CHUNK_SIZE + TAG_SIZE;
+/// Size of a nonce
+const NONCE_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    // Hex encoded 256 bit key used to wrap the keys of new files, files are not encrypted if unset
+    master_key: Option<String>,
+    // Keys which were replaced by the master key, data keys wrapped by them can still be read
+    previous_master_keys: Vec<String>,
+}
+
+/// Key encrypting the data keys of files
+#[derive(Clone)]
+struct MasterKey {
+    // Identifies the key a data key was wrapped with, derived from the key itself
+    id: String,
+    cipher: Aes256Gcm,
+}
+
+/// Key encrypting the contents of a single stored object
+#[derive(Clone)]
+pub struct DataKey {
+    cipher: Aes256Gcm,
+}
+
+/// Holds all configured master keys
+#[derive(Clone, Default)]
+pub struct Keyring {
+    current: Option<MasterKey>,
+    previous: Vec<MasterKey>,
+}
+
+/// Encrypts a file stream chunk by chunk
+pub struct EncryptingStream {
+    inner: FileStream,
+    cipher: Aes256Gcm,
+    counter: u64,
+    // Holds one byte more than a chunk to know whether the chunk is the last one
+    plain: Vec<u8>,
+    plain_len: usize,
+    encrypted: Vec<u8>,
+    encrypted_pos: usize,
+    inner_done: bool,
+    finished: bool,
+}
+
+/// Decrypts a file stream chunk by chunk, optionally only returning a range of the plaintext
+pub struct DecryptingStream {
+    inner: FileStream,
+    cipher: Aes256Gcm,
+    counter: u64,
+    // Holds one byte more than a chunk to know whether the chunk is the last one
+    encrypted: Vec<u8>,
+    encrypted_len: usize,
+    // Whether the last chunk of the object was decrypted
+    last_decrypted: bool,
+    plain: Vec<u8>,
+    plain_pos: usize,
+    // Bytes dropped from the start of the first chunk
+    skip: usize,
+    // Bytes left to return, everything is returned if not present
+    remaining: Option<u64>,
+    inner_done: bool,
+}
+
+impl MasterKey {
+    /// Parses a hex encoded master key
+    fn parse(key: &str) -> StorageResult<Self> {
+        let key = hex::decode(key.trim()).map_err(|_| StorageError::InvalidMasterKeyError)?;
+        if key.len() != 32 {
+            return Err(StorageError::InvalidMasterKeyError);
+        }
+        Ok(Self {
+            id: hex::encode(&Sha256::digest(&key)[..4]),
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        })
+    }
+
+    /// Wraps a data key, the result contains the master key id, the nonce and the encrypted key
+    fn wrap(&self, key: &Key<Aes256Gcm>) -> StorageResult<String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let wrapped = self
+            .cipher
+            .encrypt(&nonce, key.as_slice())
+            .map_err(|_| StorageError::EncryptionError)?;
+        Ok(format!(
+            "{}:{}{}",
+            self.id,
+            hex::encode(nonce),
+            hex::encode(wrapped)
+        ))
+    }
+
+    /// Unwraps a data key which was wrapped by this master key
+    fn unwrap(&self, wrapped: &str) -> StorageResult<Key<Aes256Gcm>> {
+        let bytes = hex::decode(wrapped).map_err(|_| StorageError::EncryptionError)?;
+        if bytes.len() < NONCE_SIZE {
+            return Err(StorageError::EncryptionError);
+        }
+        let (nonce, wrapped) = bytes.split_at(NONCE_SIZE);
+        let key = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), wrapped)
+            .map_err(|_| StorageError::EncryptionError)?;
+        if key.len() != 32 {
+            return Err(StorageError::EncryptionError);
+        }
+        Ok(*Key::<Aes256Gcm>::from_slice(&key))
+    }
+}
+
+impl Keyring {
+    /// Loads the master keys from the config
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The encryption config
+    pub fn from_config(config: &EncryptionConfig) -> StorageResult<Self> {
+        Ok(Self {
+            current: config
+                .master_key
+                .as_deref()
+                .map(MasterKey::parse)
+                .transpose()?,
+            previous: config
+                .previous_master_keys
+                .iter()
+                .map(|key| MasterKey::parse(key))
+                .collect::<StorageResult<_>>()?,
+        })
+    }
+
+    /// Generates a new hex encoded master key
+    pub fn generate_master_key() -> String {
+        hex::encode(Aes256Gcm::generate_key(OsRng))
+    }
+
+    /// Returns the id of the master key new data keys are wrapped with
+    pub fn current_key_id(&self) -> Option<&str> {
+        self.current.as_ref().map(|key| key.id.as_str())
+    }
+
+    /// Generates the data key of a new object
+    ///
+    /// # Returns
+    ///
+    /// The data key and its wrapped form which is stored with the file, or `None` if
+    /// encryption is disabled
+    pub fn generate_data_key(&self) -> StorageResult<Option<(DataKey, String)>> {
+        match &self.current {
+            Some(master_key) => {
+                let key = Aes256Gcm::generate_key(OsRng);
+                let wrapped = master_key.wrap(&key)?;
+                Ok(Some((
+                    DataKey {
+                        cipher: Aes256Gcm::new(&key),
+                    },
+                    wrapped,
+                )))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Unwraps the data key of a file
+    ///
+    /// # Arguments
+    ///
+    /// * `wrapped` - The wrapped data key stored with the file, `None` for unencrypted files
+    ///
+    /// # Returns
+    ///
+    /// The data key or `None` if the file is not encrypted
+    pub fn unwrap_data_key(&self, wrapped: Option<&str>) -> StorageResult<Option<DataKey>> {
+        match wrapped {
+            Some(wrapped) => Ok(Some(DataKey {
+                cipher: Aes256Gcm::new(&self.unwrap(wrapped)?),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Wraps a data key with the current master key without touching the encrypted object
+    ///
+    /// # Arguments
+    ///
+    /// * `wrapped` - The data key wrapped by any configured master key
+    ///
+    /// # Returns
+    ///
+    /// The data key wrapped by the current master key
+    pub fn rewrap_data_key(&self, wrapped: &str) -> StorageResult<String> {
+        self.current
+            .as_ref()
+            .ok_or(StorageError::UnknownMasterKeyError)?
+            .wrap(&self.unwrap(wrapped)?)
+    }
+
+    /// Unwraps a data key using the master key it was wrapped with
+    fn unwrap(&self, wrapped: &str) -> StorageResult<Key<Aes256Gcm>> {
+        let (id, wrapped) = wrapped
+            .split_once(':')
+            .ok_or(StorageError::EncryptionError)?;
+        self.current
+            .iter()
+            .chain(self.previous.iter())
+            .find(|key| key.id == id)
+            .ok_or(StorageError::UnknownMasterKeyError)?
+            .unwrap(wrapped)
+    }
+}
+
+/// Calculates the size of an object after encrypting it
+///
+/// # Arguments
+///
+/// * `size` - The plaintext size in bytes
+pub fn encrypted_size(size: u64) -> u64 {
+    // Empty files still consist of a single (empty) chunk
+    let chunks = size.div_ceil(CHUNK_SIZE as u64).max(1);
+    size + chunks * TAG_SIZE as u64
+}
+
+/// Maps a range of the plaintext to the range of encrypted chunks containing it
+///
+/// # Arguments
+///
+/// * `range` - The range of the plaintext
+pub fn encrypted_range(range: ByteRange) -> ByteRange {
+    let first_chunk = range.start / CHUNK_SIZE as u64;
+    let last_chunk = range.end / CHUNK_SIZE as u64;
+    // One byte of the following chunk is included, its presence tells the last chunk of the
+    // range apart from the last chunk of the object. The end may exceed the object, storage
+    // drivers stop at the end
+    ByteRange::new(
+        first_chunk * ENCRYPTED_CHUNK_SIZE as u64,
+        (last_chunk + 1) * ENCRYPTED_CHUNK_SIZE as u64,
+    )
+}
+
+/// Creates the nonce of a chunk following the STREAM construction, data keys are unique per
+/// object so the chunk index is enough. The last chunk is flagged, so objects truncated at a
+/// chunk boundary fail to decrypt
+fn chunk_nonce(counter: u64, last: bool) -> Nonce<<Aes256Gcm as AeadCore>::NonceSize> {
+    let mut nonce = [0_u8; NONCE_SIZE];
+    nonce[NONCE_SIZE - 9..NONCE_SIZE - 1].copy_from_slice(&counter.to_be_bytes());
+    nonce[NONCE_SIZE - 1] = last as u8;
+    nonce.into()
+}
+
+/// Error returned for objects which can't be decrypted
+fn invalid_chunk_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Failed to decrypt chunk")
+}
+
+impl EncryptingStream {
+    pub fn new(inner: FileStream, key: &DataKey) -> Self {
+        Self {
+            inner,
+            cipher: key.cipher.clone(),
+            counter: 0,
+            plain: vec![0; CHUNK_SIZE + 1],
+            plain_len: 0,
+            encrypted: Vec::new(),
+            encrypted_pos: 0,
+            inner_done: false,
+            finished: false,
+        }
+    }
+}
+
+impl AsyncRead for EncryptingStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.encrypted_pos < this.encrypted.len() {
+                let amount = buf
+                    .remaining()
+                    .min(this.encrypted.len() - this.encrypted_pos);
+                buf.put_slice(&this.encrypted[this.encrypted_pos..this.encrypted_pos + amount]);
+                this.encrypted_pos += amount;
+                return Poll::Ready(Ok(()));
+            }
+            if this.finished {
+                return Poll::Ready(Ok(()));
+            }
+
+            while this.plain_len <= CHUNK_SIZE && !this.inner_done {
+                let mut read_buf = ReadBuf::new(&mut this.plain[this.plain_len..]);
+                match this.inner.as_mut().poll_read(cx, &mut read_buf) {
+                    Poll::Ready(Ok(())) if read_buf.filled().is_empty() => this.inner_done = true,
+                    Poll::Ready(Ok(())) => this.plain_len += read_buf.filled().len(),
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            // The chunk is the last one if the byte after it is missing
+            let last = this.plain_len <= CHUNK_SIZE;
+            let chunk_len = this.plain_len.min(CHUNK_SIZE);
+            this.encrypted = this
+                .cipher
+                .encrypt(&chunk_nonce(this.counter, last), &this.plain[..chunk_len])
+                .map_err(|_| io::Error::other("Failed to encrypt chunk"))?;
+            this.encrypted_pos = 0;
+            this.counter += 1;
+            this.plain.copy_within(chunk_len..this.plain_len, 0);
+            this.plain_len -= chunk_len;
+            this.finished = last;
+        }
+    }
+}
+
+impl DecryptingStream {
+    /// Creates a decrypting stream
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The encrypted stream, starting at the chunk containing the range start
+    /// * `key` - The data key of the object
+    /// * `range` - The range of the plaintext to return, everything is returned if not present
+    pub fn new(inner: FileStream, key: &DataKey, range: Option<ByteRange>) -> Self {
+        Self {
+            inner,
+            cipher: key.cipher.clone(),
+            counter: range.map_or(0, |range| range.start / CHUNK_SIZE as u64),
+            encrypted: vec![0; ENCRYPTED_CHUNK_SIZE + 1],
+            encrypted_len: 0,
+            last_decrypted: false,
+            plain: Vec::new(),
+            plain_pos: 0,
+            skip: range.map_or(0, |range| (range.start % CHUNK_SIZE as u64) as usize),
+            remaining: range.map(|range| range.size()),
+            inner_done: false,
+        }
+    }
+}
+
+impl AsyncRead for DecryptingStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.remaining == Some(0) {
+                return Poll::Ready(Ok(()));
+            }
+            if this.plain_pos < this.plain.len() {
+                let mut amount = buf.remaining().min(this.plain.len() - this.plain_pos);
+                if let Some(remaining) = this.remaining.as_mut() {
+                    amount = amount.min(*remaining as usize);
+                    *remaining -= amount as u64;
+                }
+                buf.put_slice(&this.plain[this.plain_pos..this.plain_pos + amount]);
+                this.plain_pos += amount;
+                return Poll::Ready(Ok(()));
+            }
+            if this.last_decrypted {
+                return Poll::Ready(Ok(()));
+            }
+
+            while this.encrypted_len <= ENCRYPTED_CHUNK_SIZE && !this.inner_done {
+                let mut read_buf = ReadBuf::new(&mut this.encrypted[this.encrypted_len..]);
+                match this.inner.as_mut().poll_read(cx, &mut read_buf) {
+                    Poll::Ready(Ok(())) if read_buf.filled().is_empty() => this.inner_done = true,
+                    Poll::Ready(Ok(())) => this.encrypted_len += read_buf.filled().len(),
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            // The chunk is the last one if the byte after it is missing, a chunk which was
+            // truncated or isn't the last one fails to decrypt as such
+            let last = this.encrypted_len <= ENCRYPTED_CHUNK_SIZE;
+            let chunk_len = this.encrypted_len.min(ENCRYPTED_CHUNK_SIZE);
+            this.plain = this
+                .cipher
+                .decrypt(
+                    &chunk_nonce(this.counter, last),
+                    &this.encrypted[..chunk_len],
+                )
+                .map_err(|_| invalid_chunk_error())?;
+            this.plain_pos = this.skip.min(this.plain.len());
+            this.skip = 0;
+            this.counter += 1;
+            this.encrypted.copy_within(chunk_len..this.encrypted_len, 0);
+            this.encrypted_len -= chunk_len;
+            this.last_decrypted = last;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use rocket::tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    fn data_key() -> DataKey {
+        let keyring = Keyring::from_config(&EncryptionConfig {
+            master_key: Some(Keyring::generate_master_key()),
+            previous_master_keys: Vec::new(),
+        })
+        .unwrap();
+        keyring.generate_data_key().unwrap().unwrap().0
+    }
+
+    fn plaintext(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i % 251) as u8).collect()
+    }
+
+    async fn encrypt(key: &DataKey, plain: &[u8]) -> Vec<u8> {
+        let mut stream = EncryptingStream::new(Box::pin(Cursor::new(plain.to_vec())), key);
+        let mut encrypted = Vec::new();
+        stream.read_to_end(&mut encrypted).await.unwrap();
+        encrypted
+    }
+
+    async fn decrypt(
+        key: &DataKey,
+        encrypted: &[u8],
+        range: Option<ByteRange>,
+    ) -> io::Result<Vec<u8>> {
+        // Mirrors the storage drivers, which stop reading at the end of the object
+        let encrypted = match range.map(encrypted_range) {
+            Some(encrypted_range) => {
+                let end = (encrypted_range.end as usize + 1).min(encrypted.len());
+                encrypted[encrypted_range.start as usize..end].to_vec()
+            }
+            None => encrypted.to_vec(),
+        };
+        let mut stream = DecryptingStream::new(Box::pin(Cursor::new(encrypted)), key, range);
+        let mut plain = Vec::new();
+        stream.read_to_end(&mut plain).await?;
+        Ok(plain)
+    }
+
+    #[rocket::async_test]
+    async fn round_trips_files() {
+        let key = data_key();
+        for size in [
+            0,
+            1,
+            CHUNK_SIZE - 1,
+            CHUNK_SIZE,
+            CHUNK_SIZE + 1,
+            3 * CHUNK_SIZE,
+            3 * CHUNK_SIZE + 17,
+        ] {
+            let plain = plaintext(size);
+            let encrypted = encrypt(&key, &plain).await;
+            assert_eq!(encrypted.len() as u64, encrypted_size(size as u64));
+            assert_eq!(decrypt(&key, &encrypted, None).await.unwrap(), plain);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn decrypts_ranges() {
+        let key = data_key();
+        let size = 3 * CHUNK_SIZE + 17;
+        let plain = plaintext(size);
+        let encrypted = encrypt(&key, &plain).await;
+        for (start, end) in [
+            (0, 0),
+            (10, 20),
+            (CHUNK_SIZE - 1, CHUNK_SIZE),
+            (CHUNK_SIZE, 2 * CHUNK_SIZE - 1),
+            (CHUNK_SIZE + 5, 3 * CHUNK_SIZE + 3),
+            (3 * CHUNK_SIZE, size - 1),
+            (0, size - 1),
+        ] {
+            let range = ByteRange::new(start as u64, end as u64);
+            assert_eq!(
+                decrypt(&key, &encrypted, Some(range)).await.unwrap(),
+                plain[start..=end]
+            );
+        }
+    }
+
+    #[rocket::async_test]
+    async fn rejects_truncated_files() {
+        let key = data_key();
+        let encrypted = encrypt(&key, &plaintext(3 * CHUNK_SIZE)).await;
+        for len in [
+            0,
+            TAG_SIZE,
+            ENCRYPTED_CHUNK_SIZE,
+            2 * ENCRYPTED_CHUNK_SIZE,
+            encrypted.len() - 1,
+        ] {
+            assert!(decrypt(&key, &encrypted[..len], None).await.is_err());
+        }
+        let range = ByteRange::new(CHUNK_SIZE as u64, 3 * CHUNK_SIZE as u64 - 1);
+        assert!(
+            decrypt(&key, &encrypted[..2 * ENCRYPTED_CHUNK_SIZE], Some(range))
+                .await
+                .is_err()
+        );
+    }
+
+    #[rocket::async_test]
+    async fn rejects_tampered_files() {
+        let key = data_key();
+        let plain = plaintext(2 * CHUNK_SIZE + 5);
+        let encrypted = encrypt(&key, &plain).await;
+        for position in [0, ENCRYPTED_CHUNK_SIZE + 3, encrypted.len() - 1] {
+            let mut tampered = encrypted.clone();
+            tampered[position] ^= 1;
+            assert!(decrypt(&key, &tampered, None).await.is_err());
+        }
+
+        // Chunks can't be reordered or appended
+        let mut reordered = encrypted.clone();
+        reordered[..2 * ENCRYPTED_CHUNK_SIZE].rotate_left(ENCRYPTED_CHUNK_SIZE);
+        assert!(decrypt(&key, &reordered, None).await.is_err());
+        let mut appended = encrypted.clone();
+        appended.extend_from_slice(&encrypted[..ENCRYPTED_CHUNK_SIZE]);
+        assert!(decrypt(&key, &appended, None).await.is_err());
+
+        // Objects encrypted with another key are rejected
+        assert!(decrypt(&data_key(), &encrypted, None).await.is_err());
+    }
+}
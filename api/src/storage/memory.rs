@@ -0,0 +1,297 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Cursor,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use rocket::tokio::io::AsyncReadExt;
+
+use super::{
+    backend::StorageBackend,
+    driver::{ByteRange, FileMetadata, FileStream, StorageError, StorageResult},
+};
+
+/// Stores files in memory, once the capacity is reached new files are refused unless eviction
+/// of the least recently used files is enabled. Files are lost on restart, so this is only
+/// meant for tests and ephemeral setups
+#[derive(Debug, Clone)]
+pub struct MemoryBackend {
+    store: Arc<Mutex<MemoryStore>>,
+}
+
+#[derive(Debug)]
+struct MemoryStore {
+    files: HashMap<String, MemoryFile>,
+    // Ids of the files ordered by their last use
+    usage: BTreeMap<u64, String>,
+    // Incremented on every use, used to order the files
+    clock: u64,
+    size: u64,
+    max_size: u64,
+    // Evicted files are still referenced by their rows, so evicting is only safe if losing
+    // files is acceptable
+    evict: bool,
+}
+
+#[derive(Debug)]
+struct MemoryFile {
+    data: Arc<[u8]>,
+    content_type: String,
+    last_used: u64,
+}
+
+impl MemoryBackend {
+    /// Creates an empty memory backend
+    ///
+    /// # Arguments
+    ///
+    /// * `max_size` - The maximum amount of bytes stored
+    /// * `evict` - Whether the least recently used files are evicted once the capacity is
+    ///   reached, files which don't fit are refused otherwise
+    pub fn new(max_size: u64, evict: bool) -> Self {
+        Self {
+            store: Arc::new(Mutex::new(MemoryStore {
+                files: HashMap::new(),
+                usage: BTreeMap::new(),
+                clock: 0,
+                size: 0,
+                max_size,
+                evict,
+            })),
+        }
+    }
+
+    fn store(&self) -> std::sync::MutexGuard<'_, MemoryStore> {
+        // A panic while holding the lock can't leave the maps inconsistent in a harmful way
+        self.store.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl MemoryStore {
+    /// Marks a file as used and returns it
+    fn touch(&mut self, id: &str) -> Option<&MemoryFile> {
+        self.clock += 1;
+        let clock = self.clock;
+        let file = self.files.get_mut(id)?;
+        self.usage.remove(&file.last_used);
+        self.usage.insert(clock, id.to_string());
+        file.last_used = clock;
+        Some(file)
+    }
+
+    fn remove(&mut self, id: &str) -> Option<MemoryFile> {
+        let file = self.files.remove(id)?;
+        self.usage.remove(&file.last_used);
+        self.size -= file.data.len() as u64;
+        Some(file)
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    async fn save_file(
+        &self,
+        id: &str,
+        content_type: &str,
+        size: u64,
+        mut stream: FileStream,
+    ) -> StorageResult<()> {
+        if size > self.store().max_size {
+            return Err(StorageError::MemoryFullError);
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        stream
+            .read_to_end(&mut data)
+            .await
+            .map_err(StorageError::from)?;
+
+        let mut store = self.store();
+        // The file being replaced is kept if the new one is refused
+        let replaced = store.files.get(id).map_or(0, |file| file.data.len() as u64);
+        if data.len() as u64 > store.max_size
+            || (!store.evict && store.size - replaced + data.len() as u64 > store.max_size)
+        {
+            return Err(StorageError::MemoryFullError);
+        }
+        store.remove(id);
+        while store.size + data.len() as u64 > store.max_size {
+            let Some((_, evicted)) = store.usage.pop_first() else {
+                break;
+            };
+            store.remove(&evicted);
+        }
+
+        store.clock += 1;
+        let clock = store.clock;
+        store.size += data.len() as u64;
+        store.usage.insert(clock, id.to_string());
+        store.files.insert(
+            id.to_string(),
+            MemoryFile {
+                data: data.into(),
+                content_type: content_type.to_string(),
+                last_used: clock,
+            },
+        );
+        Ok(())
+    }
+
+    async fn get_file(
+        &self,
+        id: &str,
+        range: Option<ByteRange>,
+    ) -> StorageResult<(FileStream, String)> {
+        let (data, content_type) = {
+            let mut store = self.store();
+            let file = store.touch(id).ok_or(StorageError::MemoryLoadError)?;
+            (file.data.clone(), file.content_type.clone())
+        };
+        let mut cursor = Cursor::new(data);
+        match range {
+            Some(range) => {
+                cursor.set_position(range.start);
+                Ok((Box::pin(cursor.take(range.size())), content_type))
+            }
+            None => Ok((Box::pin(cursor), content_type)),
+        }
+    }
+
+    async fn head_file(&self, id: &str) -> StorageResult<FileMetadata> {
+        let store = self.store();
+        let file = store.files.get(id).ok_or(StorageError::MemoryLoadError)?;
+        Ok(FileMetadata {
+            content_type: file.content_type.clone(),
+            size: file.data.len() as u64,
+        })
+    }
+
+    async fn delete_file(&self, id: &str) -> StorageResult<()> {
+        self.store()
+            .remove(id)
+            .map(|_| ())
+            .ok_or(StorageError::MemoryDeleteError)
+    }
+
+    async fn list_files(&self) -> StorageResult<Vec<String>> {
+        Ok(self.store().files.keys().cloned().collect())
+    }
+
+    async fn file_exists(&self, id: &str) -> StorageResult<bool> {
+        Ok(self.store().files.contains_key(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn save(backend: &MemoryBackend, id: &str, data: &[u8]) -> StorageResult<()> {
+        backend
+            .save_file(
+                id,
+                "text/plain",
+                data.len() as u64,
+                Box::pin(Cursor::new(data.to_vec())),
+            )
+            .await
+    }
+
+    async fn read(backend: &MemoryBackend, id: &str, range: Option<ByteRange>) -> Vec<u8> {
+        let (mut stream, _) = backend
+            .get_file(id, range)
+            .await
+            .expect("file should exist");
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).await.unwrap();
+        data
+    }
+
+    async fn stored(backend: &MemoryBackend) -> Vec<String> {
+        let mut ids = backend.list_files().await.unwrap();
+        ids.sort();
+        ids
+    }
+
+    #[rocket::async_test]
+    async fn refuses_files_exceeding_capacity() {
+        let backend = MemoryBackend::new(8, false);
+        save(&backend, "a", b"12345").await.unwrap();
+        assert!(matches!(
+            save(&backend, "b", b"12345").await,
+            Err(StorageError::MemoryFullError)
+        ));
+        assert_eq!(stored(&backend).await, vec!["a"]);
+
+        save(&backend, "b", b"123").await.unwrap();
+        assert_eq!(backend.store().size, 8);
+    }
+
+    #[rocket::async_test]
+    async fn refuses_files_larger_than_capacity_even_if_evicting() {
+        let backend = MemoryBackend::new(4, true);
+        save(&backend, "a", b"1234").await.unwrap();
+        assert!(matches!(
+            save(&backend, "b", b"12345").await,
+            Err(StorageError::MemoryFullError)
+        ));
+    }
+
+    #[rocket::async_test]
+    async fn evicts_least_recently_used_files() {
+        let backend = MemoryBackend::new(9, true);
+        save(&backend, "a", b"123").await.unwrap();
+        save(&backend, "b", b"123").await.unwrap();
+        save(&backend, "c", b"123").await.unwrap();
+        // Reading a file marks it as used, so the next file is evicted instead
+        read(&backend, "a", None).await;
+
+        save(&backend, "d", b"123").await.unwrap();
+        assert_eq!(stored(&backend).await, vec!["a", "c", "d"]);
+        save(&backend, "e", b"123456").await.unwrap();
+        assert_eq!(stored(&backend).await, vec!["d", "e"]);
+        assert_eq!(backend.store().size, 9);
+    }
+
+    #[rocket::async_test]
+    async fn replaces_existing_files() {
+        let backend = MemoryBackend::new(8, false);
+        save(&backend, "a", b"12345").await.unwrap();
+        save(&backend, "a", b"1234567").await.unwrap();
+        assert_eq!(read(&backend, "a", None).await, b"1234567");
+        assert_eq!(backend.store().size, 7);
+
+        // Refused files don't replace the stored one
+        save(&backend, "b", b"1").await.unwrap();
+        assert!(save(&backend, "a", b"12345678").await.is_err());
+        assert_eq!(read(&backend, "a", None).await, b"1234567");
+    }
+
+    #[rocket::async_test]
+    async fn reads_ranges() {
+        let backend = MemoryBackend::new(16, false);
+        save(&backend, "a", b"0123456789").await.unwrap();
+        assert_eq!(
+            read(&backend, "a", Some(ByteRange::new(2, 4))).await,
+            b"234"
+        );
+        // The end may exceed the file
+        assert_eq!(
+            read(&backend, "a", Some(ByteRange::new(7, 20))).await,
+            b"789"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn deletes_files() {
+        let backend = MemoryBackend::new(16, false);
+        save(&backend, "a", b"123").await.unwrap();
+        backend.delete_file("a").await.unwrap();
+        assert!(!backend.file_exists("a").await.unwrap());
+        assert_eq!(backend.store().size, 0);
+        assert!(matches!(
+            backend.delete_file("a").await,
+            Err(StorageError::MemoryDeleteError)
+        ));
+    }
+}
@@ -0,0 +1,144 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use log::warn;
+
+use super::{
+    backend::StorageBackend,
+    driver::{ByteRange, FileMetadata, FileStream, PresignedUpload, StorageResult},
+};
+
+/// Serves files while they are copied to another backend. New files are saved in the target,
+/// files which weren't copied yet are read from the source
+pub struct MigratingBackend {
+    target: Arc<dyn StorageBackend>,
+    source: Arc<dyn StorageBackend>,
+}
+
+impl MigratingBackend {
+    /// Creates a migrating backend
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - The backend files are moved to
+    /// * `source` - The backend files were stored in before
+    pub fn new(target: Arc<dyn StorageBackend>, source: Arc<dyn StorageBackend>) -> Self {
+        Self { target, source }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MigratingBackend {
+    async fn save_file(
+        &self,
+        id: &str,
+        content_type: &str,
+        size: u64,
+        stream: FileStream,
+    ) -> StorageResult<()> {
+        self.target.save_file(id, content_type, size, stream).await
+    }
+
+    async fn get_file(
+        &self,
+        id: &str,
+        range: Option<ByteRange>,
+    ) -> StorageResult<(FileStream, String)> {
+        match self.target.get_file(id, range).await {
+            Ok(file) => Ok(file),
+            Err(_) => self.source.get_file(id, range).await,
+        }
+    }
+
+    async fn head_file(&self, id: &str) -> StorageResult<FileMetadata> {
+        match self.target.head_file(id).await {
+            Ok(metadata) => Ok(metadata),
+            Err(_) => self.source.head_file(id).await,
+        }
+    }
+
+    async fn delete_file(&self, id: &str) -> StorageResult<()> {
+        // The file is usually only stored in one of the backends, so it is only deleted from
+        // the backends storing it
+        let (target, source) = (
+            delete_existing(self.target.as_ref(), id).await,
+            delete_existing(self.source.as_ref(), id).await,
+        );
+        match (target, source) {
+            (Err(err), Err(_)) => Err(err),
+            (Err(err), Ok(_)) | (Ok(_), Err(err)) => {
+                warn!(
+                    "Failed to delete file {} from a migrated backend: {}",
+                    id, err
+                );
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn copy_file(&self, source: &str, target: &str) -> StorageResult<()> {
+        // Copies are saved in the target like new files
+        if self.target.file_exists(source).await? {
+            return self.target.copy_file(source, target).await;
+        }
+        let metadata = self.source.head_file(source).await?;
+        let (stream, _) = self.source.get_file(source, None).await?;
+        self.target
+            .save_file(target, &metadata.content_type, metadata.size, stream)
+            .await
+    }
+
+    async fn list_files(&self) -> StorageResult<Vec<String>> {
+        let mut ids: HashSet<String> = self.target.list_files().await?.into_iter().collect();
+        ids.extend(self.source.list_files().await?);
+        Ok(ids.into_iter().collect())
+    }
+
+    async fn file_exists(&self, id: &str) -> StorageResult<bool> {
+        Ok(self.target.file_exists(id).await? || self.source.file_exists(id).await?)
+    }
+
+    fn has_replicas(&self) -> bool {
+        self.target.has_replicas()
+    }
+
+    async fn repair(&self, ids: &HashSet<String>) -> StorageResult<usize> {
+        self.target.repair(ids).await
+    }
+
+    async fn presign_download(
+        &self,
+        id: &str,
+        content_type: Option<&str>,
+        content_disposition: &str,
+    ) -> StorageResult<Option<String>> {
+        // The url has to point to the backend which currently stores the file
+        let backend = match self.target.file_exists(id).await? {
+            true => &self.target,
+            false => &self.source,
+        };
+        backend
+            .presign_download(id, content_type, content_disposition)
+            .await
+    }
+
+    async fn presign_upload(
+        &self,
+        id: &str,
+        content_type: &str,
+        expiration: Duration,
+    ) -> StorageResult<Option<PresignedUpload>> {
+        self.target
+            .presign_upload(id, content_type, expiration)
+            .await
+    }
+}
+
+/// Deletes a file from a backend if it is stored there
+async fn delete_existing(backend: &dyn StorageBackend, id: &str) -> StorageResult<()> {
+    if backend.file_exists(id).await? {
+        backend.delete_file(id).await?;
+    }
+    Ok(())
+}
@@ -1,3 +1,8 @@
+pub mod backend;
 pub mod drive;
 pub mod driver;
+pub mod encryption;
+pub mod memory;
+pub mod migrating;
 pub mod object_storage;
+pub mod replicated;
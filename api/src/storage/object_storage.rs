@@ -1,40 +1,402 @@
+use std::time::Duration;
+
 use crate::s3::bucket::{Bucket, BucketOperations};
 
-use super::driver::{StorageError, StorageResult};
-use aws_sdk_s3::primitives::ByteStream;
+use super::{
+    backend::StorageBackend,
+    driver::{ByteRange, FileMetadata, FileStream, PresignedUpload, StorageError, StorageResult},
+};
+use async_trait::async_trait;
+use aws_sdk_s3::{presigning::PresigningConfig, primitives::ByteStream, types::CompletedPart};
+use futures_util::TryStreamExt;
+use http_body::Frame;
+use http_body_util::StreamBody;
+use log::error;
+use rocket::tokio::{io::AsyncReadExt, task::JoinSet};
+use tokio_util::io::ReaderStream;
+
+/// Smallest part size accepted by s3, except for the last part
+pub const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+/// Maximum amount of parts of a multipart upload
+const MAX_PARTS: u64 = 10_000;
+
+/// Stores files in an s3 compatible object storage bucket
+#[derive(Debug, Clone)]
+pub struct ObjectStorageBackend {
+    bucket: Bucket,
+    multipart: MultipartConfig,
+    // Time presigned download urls are valid for, files are proxied by the api if not present
+    presigned_download_expiration: Option<Duration>,
+    // Whether clients may upload files directly to the bucket using presigned urls
+    presigned_uploads: bool,
+}
+
+/// Decides when and how files are uploaded in multiple parts
+#[derive(Debug, Clone, Copy)]
+pub struct MultipartConfig {
+    // Files larger than this amount of bytes are uploaded in parts
+    pub threshold: u64,
+    // Size of a part in bytes, raised if the file would need more than 10000 parts
+    pub part_size: u64,
+    // Maximum amount of parts uploaded at once, each of them is buffered in memory
+    pub concurrency: usize,
+}
+
+impl Default for MultipartConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 64 * 1024 * 1024,
+            part_size: 16 * 1024 * 1024,
+            concurrency: 4,
+        }
+    }
+}
+
+impl ObjectStorageBackend {
+    pub fn new(bucket: Bucket) -> Self {
+        Self {
+            bucket,
+            multipart: MultipartConfig::default(),
+            presigned_download_expiration: None,
+            presigned_uploads: false,
+        }
+    }
+
+    /// Changes when and how files are uploaded in multiple parts
+    ///
+    /// # Arguments
+    ///
+    /// * `multipart` - The multipart config
+    pub fn with_multipart(mut self, multipart: MultipartConfig) -> Self {
+        self.multipart = multipart;
+        self
+    }
+
+    /// Lets clients download files directly from the bucket using presigned urls
+    ///
+    /// # Arguments
+    ///
+    /// * `expiration` - The time the urls are valid for
+    pub fn with_presigned_downloads(mut self, expiration: Duration) -> Self {
+        self.presigned_download_expiration = Some(expiration);
+        self
+    }
+
+    /// Lets clients upload files directly to the bucket using presigned urls
+    pub fn with_presigned_uploads(mut self) -> Self {
+        self.presigned_uploads = true;
+        self
+    }
+}
+
+#[async_trait]
+impl StorageBackend for ObjectStorageBackend {
+    async fn save_file(
+        &self,
+        id: &str,
+        content_type: &str,
+        size: u64,
+        stream: FileStream,
+    ) -> StorageResult<()> {
+        if size > self.multipart.threshold {
+            save_file_multipart(
+                &self.bucket,
+                &self.multipart,
+                id,
+                content_type,
+                size,
+                stream,
+            )
+            .await
+        } else {
+            save_file(&self.bucket, id, content_type, size, stream).await
+        }
+    }
+
+    async fn get_file(
+        &self,
+        id: &str,
+        range: Option<ByteRange>,
+    ) -> StorageResult<(FileStream, String)> {
+        get_file(&self.bucket, id, range).await
+    }
+
+    async fn head_file(&self, id: &str) -> StorageResult<FileMetadata> {
+        head_file(&self.bucket, id).await
+    }
+
+    async fn delete_file(&self, id: &str) -> StorageResult<()> {
+        delete_file(&self.bucket, id).await
+    }
+
+    async fn list_files(&self) -> StorageResult<Vec<String>> {
+        list_files(&self.bucket).await
+    }
+
+    async fn copy_file(&self, source: &str, target: &str) -> StorageResult<()> {
+        copy_file(&self.bucket, source, target).await
+    }
+
+    async fn file_exists(&self, id: &str) -> StorageResult<bool> {
+        file_exists(&self.bucket, id).await
+    }
+
+    async fn presign_download(
+        &self,
+        id: &str,
+        content_type: Option<&str>,
+        content_disposition: &str,
+    ) -> StorageResult<Option<String>> {
+        let Some(expiration) = self
+            .presigned_download_expiration
+            .filter(|_| self.bucket.supports_presigning())
+        else {
+            return Ok(None);
+        };
+        let presigning =
+            PresigningConfig::expires_in(expiration).map_err(|_| StorageError::BucketLoadError)?;
+        let request = self
+            .bucket
+            .presign_get(id, presigning, content_type, Some(content_disposition))
+            .await
+            .map_err(|_| StorageError::BucketLoadError)?;
+        Ok(Some(request.uri().to_string()))
+    }
+
+    async fn presign_upload(
+        &self,
+        id: &str,
+        content_type: &str,
+        expiration: Duration,
+    ) -> StorageResult<Option<PresignedUpload>> {
+        if !self.presigned_uploads || !self.bucket.supports_presigning() {
+            return Ok(None);
+        }
+        let presigning =
+            PresigningConfig::expires_in(expiration).map_err(|_| StorageError::BucketLoadError)?;
+        let request = self
+            .bucket
+            .presign_put(id, presigning, content_type)
+            .await
+            .map_err(|_| StorageError::BucketLoadError)?;
+        Ok(Some(PresignedUpload {
+            url: request.uri().to_string(),
+            headers: request
+                .headers()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        }))
+    }
+}
 
 /// Implements save_file for the ObjectStorage type
 pub(crate) async fn save_file(
     bucket: &Bucket,
     id: &str,
     content_type: &str,
-    bytes: Vec<u8>,
+    size: u64,
+    stream: FileStream,
 ) -> StorageResult<()> {
     bucket
-        .put(&id, ByteStream::from(bytes), Some(&content_type))
+        .put(id, to_byte_stream(stream), size, Some(content_type))
         .await
         .map_err(|_| StorageError::BucketSaveError)
         .map(|_| ())
 }
 
+/// Implements save_file for the ObjectStorage type using a multipart upload, parts are
+/// uploaded in parallel and retried on their own. The upload is aborted if any part fails,
+/// so no parts are left behind in the bucket
+pub(crate) async fn save_file_multipart(
+    bucket: &Bucket,
+    config: &MultipartConfig,
+    id: &str,
+    content_type: &str,
+    size: u64,
+    stream: FileStream,
+) -> StorageResult<()> {
+    let upload_id = bucket
+        .create_multipart(id, Some(content_type))
+        .await
+        .map_err(|_| StorageError::BucketSaveError)?
+        .upload_id
+        .ok_or(StorageError::BucketSaveError)?;
+
+    let part_size = config
+        .part_size
+        .max(MIN_PART_SIZE)
+        .max(size.div_ceil(MAX_PARTS));
+    let uploaded = upload_parts(
+        bucket,
+        id,
+        &upload_id,
+        part_size,
+        config.concurrency.max(1),
+        stream,
+    )
+    .await;
+    let completed = match uploaded {
+        Ok(parts) => bucket
+            .complete_multipart(id, &upload_id, parts)
+            .await
+            .map_err(|_| StorageError::BucketSaveError)
+            .map(|_| ()),
+        Err(err) => Err(err),
+    };
+    if completed.is_err() {
+        if let Err(err) = bucket.abort_multipart(id, &upload_id).await {
+            error!("Failed to abort multipart upload of file {}: {}", id, err);
+        }
+    }
+    completed
+}
+
+/// Reads the stream in parts and uploads them in the background, at most `concurrency`
+/// parts are in flight. Remaining uploads are cancelled if a part fails
+///
+/// # Returns
+///
+/// The uploaded parts ordered by their part number
+async fn upload_parts(
+    bucket: &Bucket,
+    id: &str,
+    upload_id: &str,
+    part_size: u64,
+    concurrency: usize,
+    mut stream: FileStream,
+) -> StorageResult<Vec<CompletedPart>> {
+    let mut parts = Vec::new();
+    let mut in_flight = JoinSet::new();
+    let mut part_number = 0;
+    loop {
+        let mut part = Vec::with_capacity(part_size as usize);
+        (&mut stream)
+            .take(part_size)
+            .read_to_end(&mut part)
+            .await
+            .map_err(StorageError::from)?;
+        // Empty files still need a single part
+        if part.is_empty() && part_number > 0 {
+            break;
+        }
+
+        part_number += 1;
+        let length = part.len() as u64;
+        let (bucket, id, upload_id, number) = (
+            bucket.clone(),
+            id.to_string(),
+            upload_id.to_string(),
+            part_number,
+        );
+        in_flight.spawn(async move {
+            // Parts are kept in memory, so the sdk can retry them without restarting the upload
+            let output = bucket
+                .upload_part(&id, &upload_id, number, ByteStream::from(part), length)
+                .await
+                .map_err(|_| StorageError::BucketSaveError)?;
+            Ok::<_, StorageError>(
+                CompletedPart::builder()
+                    .part_number(number)
+                    .set_e_tag(output.e_tag)
+                    .build(),
+            )
+        });
+        if in_flight.len() >= concurrency {
+            if let Some(part) = in_flight.join_next().await {
+                parts.push(part.map_err(|_| StorageError::BucketSaveError)??);
+            }
+        }
+        if length < part_size {
+            break;
+        }
+    }
+    while let Some(part) = in_flight.join_next().await {
+        parts.push(part.map_err(|_| StorageError::BucketSaveError)??);
+    }
+    parts.sort_by_key(|part| part.part_number);
+    Ok(parts)
+}
+
 /// Implements get_file for the ObjectStorage type
-pub(crate) async fn get_file(bucket: &Bucket, id: &str) -> StorageResult<(Vec<u8>, String)> {
-    let data = bucket.get(&id).await.unwrap();
-    let file_type = &data.content_type.ok_or(StorageError::BucketSaveError)?;
-    let file_bytes = data
-        .body
-        .collect()
+pub(crate) async fn get_file(
+    bucket: &Bucket,
+    id: &str,
+    range: Option<ByteRange>,
+) -> StorageResult<(FileStream, String)> {
+    let range = range.map(|range| format!("bytes={}-{}", range.start, range.end));
+    let data = bucket
+        .get(id, range.as_deref())
+        .await
+        .map_err(|_| StorageError::BucketLoadError)?;
+    let file_type = data.content_type.ok_or(StorageError::BucketLoadError)?;
+    Ok((Box::pin(data.body.into_async_read()), file_type))
+}
+
+/// Implements head_file for the ObjectStorage type
+pub(crate) async fn head_file(bucket: &Bucket, id: &str) -> StorageResult<FileMetadata> {
+    let data = bucket
+        .head(id)
         .await
-        .map_err(|_| StorageError::BucketLoadError)?
-        .to_vec();
-    Ok((file_bytes, file_type.to_string()))
+        .map_err(|_| StorageError::BucketLoadError)?;
+    Ok(FileMetadata {
+        content_type: data.content_type.ok_or(StorageError::BucketLoadError)?,
+        size: data.content_length.unwrap_or_default() as u64,
+    })
 }
 
 /// Implements delete_file for the ObjectStorage type
 pub(crate) async fn delete_file(bucket: &Bucket, id: &str) -> StorageResult<()> {
     bucket
-        .delete(&id)
+        .delete(id)
         .await
         .map_err(|_| StorageError::BucketDeleteError)
         .map(|_| ())
 }
+
+/// Implements copy_file for the ObjectStorage type
+pub(crate) async fn copy_file(bucket: &Bucket, source: &str, target: &str) -> StorageResult<()> {
+    bucket
+        .copy(source, target)
+        .await
+        .map_err(|_| StorageError::BucketSaveError)
+        .map(|_| ())
+}
+
+/// Implements list_files for the ObjectStorage type
+pub(crate) async fn list_files(bucket: &Bucket) -> StorageResult<Vec<String>> {
+    let mut ids = Vec::new();
+    let mut continuation_token = None;
+    loop {
+        let page = bucket
+            .list(continuation_token.as_deref())
+            .await
+            .map_err(|_| StorageError::BucketLoadError)?;
+        ids.extend(
+            page.contents()
+                .iter()
+                .filter_map(|object| object.key())
+                .filter_map(|key| bucket.strip_key_prefix(key))
+                .map(String::from),
+        );
+        match page.next_continuation_token() {
+            Some(token) => continuation_token = Some(token.to_string()),
+            None => return Ok(ids),
+        }
+    }
+}
+
+/// Implements file_exists for the ObjectStorage type
+pub(crate) async fn file_exists(bucket: &Bucket, id: &str) -> StorageResult<bool> {
+    match bucket.head(id).await {
+        Ok(_) => Ok(true),
+        Err(err) if err.as_service_error().is_some_and(|err| err.is_not_found()) => Ok(false),
+        Err(_) => Err(StorageError::BucketLoadError),
+    }
+}
+
+/// Wraps a file stream into a byte stream which can be sent to the bucket without buffering it
+fn to_byte_stream(stream: FileStream) -> ByteStream {
+    ByteStream::from_body_1_x(StreamBody::new(
+        ReaderStream::new(stream).map_ok(Frame::data),
+    ))
+}
@@ -0,0 +1,326 @@
+use std::{collections::HashSet, path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use log::{error, info, warn};
+use rocket::tokio::{self, fs::File, io};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::{
+    backend::StorageBackend,
+    driver::{ByteRange, FileMetadata, FileStream, StorageError, StorageResult},
+};
+
+/// Decides when saving a file in a replicated backend is successful
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WritePolicy {
+    // Every replica has to store the file
+    #[default]
+    All,
+    // The majority of replicas has to store the file
+    Quorum,
+    // The primary (first) replica has to store the file, the others receive it in the background
+    Primary,
+}
+
+/// A backend which is part of a replicated backend
+#[derive(Clone)]
+pub struct Replica {
+    name: String,
+    backend: Arc<dyn StorageBackend>,
+}
+
+/// Stores every file in multiple backends, reads fall back to the next replica if one fails
+pub struct ReplicatedBackend {
+    replicas: Vec<Replica>,
+    policy: WritePolicy,
+}
+
+/// Copy of a file stream in a temporary file, so it can be read once for every replica which
+/// is written at the same time. The file is deleted once the spool is dropped
+struct Spool {
+    path: PathBuf,
+}
+
+impl Replica {
+    pub fn new(name: String, backend: Arc<dyn StorageBackend>) -> Self {
+        Self { name, backend }
+    }
+}
+
+impl Spool {
+    async fn create(mut stream: FileStream) -> StorageResult<Self> {
+        let spool = Self {
+            path: std::env::temp_dir().join(format!("uploader-spool-{}", Uuid::new_v4())),
+        };
+        let mut file = File::create(&spool.path)
+            .await
+            .map_err(StorageError::from)?;
+        io::copy(&mut stream, &mut file)
+            .await
+            .map_err(StorageError::from)?;
+        Ok(spool)
+    }
+
+    async fn open(&self) -> StorageResult<FileStream> {
+        let file = File::open(&self.path).await.map_err(StorageError::from)?;
+        Ok(Box::pin(file))
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let path = std::mem::take(&mut self.path);
+        tokio::spawn(async move {
+            let _ = tokio::fs::remove_file(path).await;
+        });
+    }
+}
+
+impl ReplicatedBackend {
+    /// Creates a replicated backend
+    ///
+    /// # Arguments
+    ///
+    /// * `replicas` - The replicas, the first one is the primary
+    /// * `policy` - Decides when saving a file is successful
+    pub fn new(replicas: Vec<Replica>, policy: WritePolicy) -> Self {
+        Self { replicas, policy }
+    }
+
+    /// Copies a file into a replica
+    async fn save_to(
+        replica: &Replica,
+        spool: &Spool,
+        id: &str,
+        content_type: &str,
+        size: u64,
+    ) -> StorageResult<()> {
+        replica
+            .backend
+            .save_file(id, content_type, size, spool.open().await?)
+            .await
+            .inspect_err(|err| {
+                error!(
+                    "Failed to save file {} in replica {}: {}",
+                    id, replica.name, err
+                )
+            })
+    }
+
+    /// Copies a file from one replica into another
+    async fn copy_between(source: &Replica, target: &Replica, id: &str) -> StorageResult<()> {
+        let metadata = source.backend.head_file(id).await?;
+        let (stream, content_type) = source.backend.get_file(id, None).await?;
+        target
+            .backend
+            .save_file(id, &content_type, metadata.size, stream)
+            .await
+    }
+}
+
+#[async_trait]
+impl StorageBackend for ReplicatedBackend {
+    async fn save_file(
+        &self,
+        id: &str,
+        content_type: &str,
+        size: u64,
+        stream: FileStream,
+    ) -> StorageResult<()> {
+        // Only the primary reads the stream, other replicas copy the file from it afterwards
+        if self.policy == WritePolicy::Primary || self.replicas.len() == 1 {
+            let (primary, replicas) = self
+                .replicas
+                .split_first()
+                .ok_or(StorageError::ReplicationError)?;
+            primary
+                .backend
+                .save_file(id, content_type, size, stream)
+                .await
+                .inspect_err(|err| {
+                    error!(
+                        "Failed to save file {} in replica {}: {}",
+                        id, primary.name, err
+                    )
+                })?;
+            if replicas.is_empty() {
+                return Ok(());
+            }
+
+            let (primary, replicas) = (primary.clone(), replicas.to_vec());
+            let id = id.to_string();
+            tokio::spawn(async move {
+                for replica in &replicas {
+                    // Missing copies are restored by the repair job
+                    if let Err(err) = Self::copy_between(&primary, replica, &id).await {
+                        error!(
+                            "Failed to copy file {} from replica {} to {}: {}",
+                            id, primary.name, replica.name, err
+                        );
+                    }
+                }
+            });
+            return Ok(());
+        }
+
+        let spool = Spool::create(stream).await?;
+
+        let results = join_all(
+            self.replicas
+                .iter()
+                .map(|replica| Self::save_to(replica, &spool, id, content_type, size)),
+        )
+        .await;
+        let saved = results.iter().filter(|result| result.is_ok()).count();
+        let required = match self.policy {
+            WritePolicy::Quorum => self.replicas.len() / 2 + 1,
+            _ => self.replicas.len(),
+        };
+        if saved < required {
+            // The upload fails, so the copies which were saved would never be used
+            for (replica, _) in self
+                .replicas
+                .iter()
+                .zip(results)
+                .filter(|(_, result)| result.is_ok())
+            {
+                let _ = replica.backend.delete_file(id).await;
+            }
+            return Err(StorageError::ReplicationError);
+        }
+        Ok(())
+    }
+
+    async fn get_file(
+        &self,
+        id: &str,
+        range: Option<ByteRange>,
+    ) -> StorageResult<(FileStream, String)> {
+        let mut last_error = StorageError::ReplicationError;
+        for replica in &self.replicas {
+            match replica.backend.get_file(id, range).await {
+                Ok(file) => return Ok(file),
+                Err(err) => {
+                    warn!(
+                        "Failed to load file {} from replica {}: {}",
+                        id, replica.name, err
+                    );
+                    last_error = err;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    async fn head_file(&self, id: &str) -> StorageResult<FileMetadata> {
+        let mut last_error = StorageError::ReplicationError;
+        for replica in &self.replicas {
+            match replica.backend.head_file(id).await {
+                Ok(metadata) => return Ok(metadata),
+                Err(err) => last_error = err,
+            }
+        }
+        Err(last_error)
+    }
+
+    async fn delete_file(&self, id: &str) -> StorageResult<()> {
+        let results = join_all(
+            self.replicas
+                .iter()
+                .map(|replica| replica.backend.delete_file(id)),
+        )
+        .await;
+        // Replicas may not have received the file yet, so deleting it from any is enough
+        let mut last_error = None;
+        let mut deleted = false;
+        for (replica, result) in self.replicas.iter().zip(results) {
+            match result {
+                Ok(_) => deleted = true,
+                Err(err) => {
+                    warn!(
+                        "Failed to delete file {} from replica {}: {}",
+                        id, replica.name, err
+                    );
+                    last_error = Some(err);
+                }
+            }
+        }
+        match (deleted, last_error) {
+            (false, Some(err)) => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list_files(&self) -> StorageResult<Vec<String>> {
+        let mut ids = HashSet::new();
+        for replica in &self.replicas {
+            ids.extend(replica.backend.list_files().await?);
+        }
+        Ok(ids.into_iter().collect())
+    }
+
+    async fn file_exists(&self, id: &str) -> StorageResult<bool> {
+        for replica in &self.replicas {
+            if replica.backend.file_exists(id).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn has_replicas(&self) -> bool {
+        self.replicas.len() > 1
+    }
+
+    async fn repair(&self, ids: &HashSet<String>) -> StorageResult<usize> {
+        // Objects of deleted files may still be stored by some replicas, they must not be
+        // brought back
+        let mut listings = Vec::with_capacity(self.replicas.len());
+        for replica in &self.replicas {
+            let listing: HashSet<String> = replica
+                .backend
+                .list_files()
+                .await?
+                .into_iter()
+                .filter(|id| ids.contains(id))
+                .collect();
+            listings.push(listing);
+        }
+
+        let mut repaired = 0;
+        for (replica, ids) in self.replicas.iter().zip(&listings) {
+            let missing = listings
+                .iter()
+                .flatten()
+                .filter(|id| !ids.contains(*id))
+                .collect::<HashSet<_>>();
+            for id in missing {
+                let Some((source, _)) = self
+                    .replicas
+                    .iter()
+                    .zip(&listings)
+                    .find(|(_, ids)| ids.contains(id))
+                else {
+                    continue;
+                };
+                match Self::copy_between(source, replica, id).await {
+                    Ok(_) => {
+                        info!(
+                            "Copied file {} from replica {} to {}",
+                            id, source.name, replica.name
+                        );
+                        repaired += 1;
+                    }
+                    Err(err) => error!(
+                        "Failed to copy file {} from replica {} to {}: {}",
+                        id, source.name, replica.name, err
+                    ),
+                }
+            }
+        }
+        Ok(repaired)
+    }
+}